@@ -0,0 +1,51 @@
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use axum::routing::get;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::indexation::Analyzer;
+use crate::server::AppState;
+
+#[derive(Deserialize)]
+pub struct AnalyzeQuery {
+    #[serde(default)]
+    text: String,
+    /// Name of the registered analyzer, like `ngram2` or `email_prefix`, `ngram2` by default.
+    analyzer: Option<String>,
+    /// Tokens of the text as a query term rather than as indexed, which differ for the
+    /// analyzers indexing prefixes.
+    search: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct AnalyzedToken {
+    text: String,
+    position: usize,
+}
+
+pub fn analyze_routes() -> Router<AppState> {
+    Router::new()
+        .route("/analyze", get(analyze))
+}
+
+/// Runs the text through an analyzer answering the tokens it's indexed or searched as, to debug
+/// why a query doesn't match.
+pub async fn analyze(State(state): State<AppState>, analyze_query: Query<AnalyzeQuery>) -> Response {
+    let analyzer = analyze_query.analyzer.as_deref().unwrap_or(Analyzer::Ngram2.tokenizer_name());
+    let search = analyze_query.search.unwrap_or(false);
+
+    // Every index registers the same analyzers
+    match state.question_index_handle.analyze(analyzer, &analyze_query.text, search) {
+        Some(tokens) => {
+            let tokens: Vec<AnalyzedToken> = tokens.into_iter()
+                .map(|(text, position)| AnalyzedToken { text, position })
+                .collect();
+            (StatusCode::OK, Json(json!({ "tokens": tokens }))).into_response()
+        }
+        None => (StatusCode::BAD_REQUEST, Json(json!({ "error": format!("unknown analyzer {}", analyzer) }))).into_response(),
+    }
+}
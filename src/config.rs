@@ -0,0 +1,561 @@
+use std::collections::HashMap;
+use std::{env, fs};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tantivy::Score;
+use tantivy::store::{Compressor, ZstdCompressor};
+
+use crate::indexation::{Bm25, DEFAULT_MAX_TOKEN_LEN, IndexMode, MergeSettings};
+use crate::indexation::handle::{MAX_SEARCH_LIMIT, MAX_SEARCH_OFFSET};
+use crate::question::indexation::is_valid_lang;
+
+const DEFAULT_BIND_ADDR: &str = "0.0.0.0:8079";
+pub const DEFAULT_MAX_BODY_BYTES: usize = 8 * 1024 * 1024;
+const DEFAULT_REINDEX_RETRY_AFTER_SECS: u64 = 30;
+const DEFAULT_COMMIT_INTERVAL_SECS: u64 = 30;
+const DEFAULT_CHANNEL_CAPACITY: usize = 8;
+const DEFAULT_EXPIRY_SWEEP_INTERVAL_SECS: u64 = 300;
+const DEFAULT_WRITER_MEMORY_BYTES: usize = 50_000_000;
+/// Tantivy refuses smaller writer budgets.
+const MIN_WRITER_MEMORY_BYTES: usize = 15_000_000;
+/// Tantivy refuses writer threads getting a smaller share of the writer budget.
+const MIN_WRITER_MEMORY_BYTES_PER_THREAD: usize = 3_000_000;
+const DEFAULT_QUERY_CACHE_TTL_SECS: u64 = 60;
+const DEFAULT_SEARCH_LIMIT: usize = 10;
+const DEFAULT_RECENCY_HALF_LIFE_SECS: u64 = 30 * 24 * 60 * 60;
+
+#[derive(Debug, Clone)]
+pub struct AppEnv {
+    backend_env: String,
+}
+
+impl AppEnv {
+    fn new(backend_env: String) -> Self {
+        AppEnv {
+            backend_env
+        }
+    }
+
+    pub fn is_prod(&self) -> bool {
+        self.backend_env.eq_ignore_ascii_case("prod")
+    }
+}
+
+/// Where the indexes live, taken from DIRECTORY. `mmap`, the default, persists them under
+/// INDEX_DIR while `ram` keeps them in memory for ephemeral deployments.
+#[derive(Clone, Copy, Debug)]
+pub enum DirectoryKind {
+    Mmap,
+    Ram,
+}
+
+/// Every setting of the server, read once from the environment at startup and passed down
+/// from there, so a wrong value stops the boot instead of failing the first request using it.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// BACKEND_SEARCH_ENV, `development` by default.
+    pub app_env: AppEnv,
+    /// BIND_ADDR, `0.0.0.0:8079` by default.
+    pub bind_addr: SocketAddr,
+    pub directory: DirectoryKind,
+    /// INDEX_DIR, holding one directory per index, `index` by default.
+    pub index_dir: PathBuf,
+    /// LOG_FORMAT=json logs one json object per line.
+    pub log_json: bool,
+    /// OTEL_EXPORTER_OTLP_ENDPOINT, collector the spans are exported to.
+    pub otlp_endpoint: Option<String>,
+    /// QUERY_LOG_PATH, file the searches are appended to.
+    pub query_log_path: Option<String>,
+    /// TLS_CERT_PATH and TLS_KEY_PATH, PEM files of the certificate and its key.
+    pub tls: Option<(String, String)>,
+    /// TCP_NODELAY, false by default.
+    pub tcp_nodelay: bool,
+    /// TCP_KEEPALIVE_SECS.
+    pub tcp_keepalive: Option<Duration>,
+    /// HTTP1_KEEPALIVE, true by default.
+    pub http1_keepalive: bool,
+    /// HTTP2_KEEPALIVE_INTERVAL_SECS.
+    pub http2_keepalive_interval: Option<Duration>,
+    /// SHUTDOWN_TIMEOUT_SECS, in-flight requests are waited for indefinitely when unset.
+    pub shutdown_timeout: Option<Duration>,
+    pub server: ServerConfig,
+    pub indexer: IndexerConfig,
+}
+
+/// Settings of the routes.
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// MAX_BODY_BYTES, 8MB by default.
+    pub max_body_bytes: usize,
+    /// CORS_ALLOWED_ORIGINS, a comma-separated list or `*` for any origin. No origin is allowed
+    /// when empty.
+    pub cors_allowed_origins: String,
+    /// INDEX_API_KEY, every route changing an index is public when unset.
+    pub index_api_key: Option<Arc<str>>,
+    /// REINDEX_RETRY_AFTER_SECS, 30 by default.
+    pub reindex_retry_after_secs: u64,
+    /// UNIFIED_QUESTIONS_WEIGHT and UNIFIED_PEOPLE_WEIGHT, multiplying the scores of each type
+    /// before the unified search ranks them together, 1.0 by default.
+    pub unified_weights: UnifiedWeights,
+    /// MAX_CONCURRENT_SEARCHES, searches beyond it are answered a 503 right away instead of
+    /// queueing. Unlimited when unset.
+    pub max_concurrent_searches: Option<usize>,
+    /// MAX_QUESTION_LEN, questions longer than this many characters are rejected. Unbounded
+    /// when unset.
+    pub max_question_len: Option<usize>,
+    /// LANG_DETECTION=false leaves the language of the questions indexed without one unset
+    /// instead of detecting it from their text, true by default.
+    pub lang_detection: bool,
+    /// DEFAULT_LANG, ISO 639-1 code of the questions indexed without a language nor a detected
+    /// one, left unset when empty.
+    pub default_lang: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct UnifiedWeights {
+    pub questions: Score,
+    pub people: Score,
+}
+
+impl Default for UnifiedWeights {
+    fn default() -> Self {
+        UnifiedWeights { questions: 1.0, people: 1.0 }
+    }
+}
+
+/// Settings shared by every index actor and handle.
+#[derive(Clone, Debug)]
+pub struct IndexerConfig {
+    /// READ_ONLY=true only serves searches over existing indexes, without any index writer.
+    /// The writer lock is never taken, so a warm standby can serve the index directory another
+    /// process still writes to, its searches following the commits of that writer.
+    pub read_only: bool,
+    /// INDEX_MODE, `open_or_create` by default.
+    pub mode: IndexMode,
+    pub merge: MergeSettings,
+    /// SEARCH_THREADS, segments are collected by a single thread by default.
+    pub search_threads: Option<usize>,
+    /// COMMIT_EVERY_DOCS.
+    pub commit_every_docs: Option<usize>,
+    /// COMMIT_INTERVAL_SECS, 30 by default.
+    pub commit_interval: Duration,
+    /// INDEX_CHANNEL_CAPACITY, messages queued for the index actor before senders wait, 8 by
+    /// default. The commit timer has its own channel.
+    pub channel_capacity: usize,
+    /// EXPIRY_SWEEP_INTERVAL_SECS, how often the expired documents are deleted, 300 by default.
+    pub expiry_sweep_interval: Duration,
+    /// SEGMENT_TOP_DOCS, hits kept per segment while searching, the search limit by default.
+    /// Lower uses less memory on large limits over many segments, at the cost of accuracy: a
+    /// segment holding more of the best hits than this loses the rest, replaced by worse ones.
+    pub segment_top_docs: Option<usize>,
+    /// RECENCY_HALF_LIFE_SECS, the age halving the relevance of a hit in the searches boosting
+    /// the recent ones, 30 days by default.
+    pub recency_half_life: Duration,
+    /// DEFAULT_SEARCH_LIMIT, hits answered by the searches not asking for a `limit`, 10 by
+    /// default and at most 100.
+    pub default_limit: usize,
+    /// DEFAULT_SEARCH_OFFSET, best hits skipped by the searches not asking for an `offset`, 0 by
+    /// default and at most 10000.
+    pub default_offset: usize,
+    /// WRITER_MEMORY_BYTES, 50MB by default.
+    pub writer_memory_bytes: usize,
+    /// INDEX_WRITER_THREADS, chosen by tantivy from the cores and the writer budget by default.
+    /// Each thread gets an equal share of WRITER_MEMORY_BYTES, which must be at least 3MB.
+    pub writer_threads: Option<usize>,
+    /// STORE_COMPRESSION, `lz4`, `zstd` or `none`, the codec of the stored fields of the indexes
+    /// created from now on, lz4 by default. An existing index keeps its own one, so changing it
+    /// takes a reindex, like with INDEX_MODE=create.
+    pub store_compression: Compressor,
+    /// REINDEX_URL, the Go backend endpoint the index name is appended to.
+    pub reindex_url: String,
+    /// BACKEND_HEALTH_URL, checked before erasing an index so it's only erased once the Go
+    /// backend can send its documents again. Never checked when empty.
+    pub backend_health_url: Option<String>,
+    /// MAX_TOKEN_LEN, 40 by default.
+    pub max_token_len: usize,
+    /// WARMUP_ON_START, false by default.
+    pub warmup_on_start: bool,
+    /// SEARCH_TIMEOUT_MS.
+    pub search_timeout: Option<Duration>,
+    /// MAX_QUERY_TERMS, queries of any length are searched when unset.
+    pub max_query_terms: Option<usize>,
+    /// BM25_K1 and BM25_B.
+    pub bm25: Option<Bm25>,
+    /// QUERY_CACHE_SIZE, no cache when unset.
+    pub query_cache_size: Option<usize>,
+    /// QUERY_CACHE_TTL_SECS, 60 by default.
+    pub query_cache_ttl: Duration,
+    /// Boost of the searched fields per index name, read from the JSON object of the
+    /// BOOSTS_FILE, like `{"questions": {"question": 2.0}}`. Unlisted fields keep a boost of 1.0.
+    pub field_boosts: HashMap<String, HashMap<String, Score>>,
+}
+
+/// Every invalid setting found, not only the first one.
+#[derive(Debug)]
+pub struct ConfigError(Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration: {}", self.0.join("; "))
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let mut vars = EnvVars::default();
+
+        let app_env = AppEnv::new(vars.string("BACKEND_SEARCH_ENV").unwrap_or_else(|| String::from("development")));
+        let bind_addr = vars.parse("BIND_ADDR", "an address like 0.0.0.0:8079").unwrap_or_else(|| DEFAULT_BIND_ADDR.parse().unwrap());
+        let directory = match vars.string("DIRECTORY").as_deref() {
+            Some("mmap") | None => DirectoryKind::Mmap,
+            Some("ram") => DirectoryKind::Ram,
+            Some(other) => {
+                vars.invalid(format!("DIRECTORY must be mmap or ram, got: {}", other));
+                DirectoryKind::Mmap
+            }
+        };
+        let tls = match (vars.string("TLS_CERT_PATH"), vars.string("TLS_KEY_PATH")) {
+            (Some(cert_path), Some(key_path)) => Some((cert_path, key_path)),
+            (None, None) => None,
+            _ => {
+                vars.invalid(String::from("TLS_CERT_PATH and TLS_KEY_PATH must be set together to enable TLS"));
+                None
+            }
+        };
+
+        let server = ServerConfig {
+            max_body_bytes: vars.parse("MAX_BODY_BYTES", "a number of bytes").unwrap_or(DEFAULT_MAX_BODY_BYTES),
+            cors_allowed_origins: vars.string("CORS_ALLOWED_ORIGINS").unwrap_or_default(),
+            index_api_key: vars.string("INDEX_API_KEY").filter(|key| !key.is_empty()).map(Arc::from),
+            reindex_retry_after_secs: vars.parse("REINDEX_RETRY_AFTER_SECS", "a number of seconds").unwrap_or(DEFAULT_REINDEX_RETRY_AFTER_SECS),
+            unified_weights: UnifiedWeights {
+                questions: vars.weight("UNIFIED_QUESTIONS_WEIGHT"),
+                people: vars.weight("UNIFIED_PEOPLE_WEIGHT"),
+            },
+            max_concurrent_searches: vars.parse("MAX_CONCURRENT_SEARCHES", "a number of searches"),
+            max_question_len: vars.parse("MAX_QUESTION_LEN", "a number of characters"),
+            lang_detection: vars.flag("LANG_DETECTION").unwrap_or(true),
+            default_lang: vars.string("DEFAULT_LANG").filter(|lang| !lang.is_empty()).map(|lang| lang.to_ascii_lowercase()),
+        };
+        if server.max_concurrent_searches == Some(0) {
+            vars.invalid(String::from("MAX_CONCURRENT_SEARCHES must be greater than 0"));
+        }
+        if server.max_question_len == Some(0) {
+            vars.invalid(String::from("MAX_QUESTION_LEN must be greater than 0"));
+        }
+        if !is_valid_lang(server.default_lang.as_deref()) {
+            vars.invalid(format!("DEFAULT_LANG must be an ISO 639-1 code like es, got: {}", server.default_lang.as_deref().unwrap_or_default()));
+        }
+
+        let indexer = IndexerConfig::from_vars(&mut vars, &app_env);
+
+        let config = Config {
+            bind_addr,
+            directory,
+            index_dir: vars.string("INDEX_DIR").map(PathBuf::from).unwrap_or_else(|| PathBuf::from("index")),
+            log_json: vars.string("LOG_FORMAT").map(|f| f.eq_ignore_ascii_case("json")).unwrap_or(false),
+            otlp_endpoint: vars.string("OTEL_EXPORTER_OTLP_ENDPOINT"),
+            query_log_path: vars.string("QUERY_LOG_PATH"),
+            tls,
+            tcp_nodelay: vars.flag("TCP_NODELAY").unwrap_or(false),
+            tcp_keepalive: vars.secs("TCP_KEEPALIVE_SECS"),
+            http1_keepalive: vars.flag("HTTP1_KEEPALIVE").unwrap_or(true),
+            http2_keepalive_interval: vars.secs("HTTP2_KEEPALIVE_INTERVAL_SECS"),
+            shutdown_timeout: vars.secs("SHUTDOWN_TIMEOUT_SECS"),
+            app_env,
+            server,
+            indexer,
+        };
+
+        match vars.errors.is_empty() {
+            true => Ok(config),
+            false => Err(ConfigError(vars.errors)),
+        }
+    }
+}
+
+impl IndexerConfig {
+    fn from_vars(vars: &mut EnvVars, app_env: &AppEnv) -> Self {
+        let mode = match vars.string("INDEX_MODE").as_deref() {
+            Some("open_or_create") | None => IndexMode::OpenOrCreate,
+            Some("open") => IndexMode::Open,
+            Some("create") => IndexMode::Create,
+            Some(other) => {
+                vars.invalid(format!("INDEX_MODE must be open, create or open_or_create, got: {}", other));
+                IndexMode::OpenOrCreate
+            }
+        };
+
+        let writer_memory_bytes = vars.parse("WRITER_MEMORY_BYTES", "a number of bytes").unwrap_or(DEFAULT_WRITER_MEMORY_BYTES);
+        if writer_memory_bytes < MIN_WRITER_MEMORY_BYTES {
+            vars.invalid(format!("WRITER_MEMORY_BYTES must be at least {}, got: {}", MIN_WRITER_MEMORY_BYTES, writer_memory_bytes));
+        }
+
+        let writer_threads = vars.parse("INDEX_WRITER_THREADS", "a number of threads");
+        match writer_threads {
+            Some(0) => vars.invalid(String::from("INDEX_WRITER_THREADS must be greater than 0")),
+            Some(threads) if writer_memory_bytes / threads < MIN_WRITER_MEMORY_BYTES_PER_THREAD => {
+                vars.invalid(format!("INDEX_WRITER_THREADS of {} leaves less than {} bytes of WRITER_MEMORY_BYTES per thread", threads, MIN_WRITER_MEMORY_BYTES_PER_THREAD));
+            }
+            _ => {}
+        }
+
+        let store_compression = match vars.string("STORE_COMPRESSION").as_deref() {
+            Some("lz4") => Compressor::Lz4,
+            Some("zstd") => Compressor::Zstd(ZstdCompressor::default()),
+            Some("none") => Compressor::None,
+            None => Compressor::default(),
+            Some(other) => {
+                vars.invalid(format!("STORE_COMPRESSION must be lz4, zstd or none, got: {}", other));
+                Compressor::default()
+            }
+        };
+
+        let commit_every_docs = vars.parse("COMMIT_EVERY_DOCS", "a number of documents");
+        let commit_interval = vars.secs("COMMIT_INTERVAL_SECS").unwrap_or(Duration::from_secs(DEFAULT_COMMIT_INTERVAL_SECS));
+        if commit_interval.is_zero() {
+            vars.invalid(String::from("COMMIT_INTERVAL_SECS must be greater than 0"));
+        }
+
+        let channel_capacity = vars.parse("INDEX_CHANNEL_CAPACITY", "a number of messages").unwrap_or(DEFAULT_CHANNEL_CAPACITY);
+        if channel_capacity == 0 {
+            vars.invalid(String::from("INDEX_CHANNEL_CAPACITY must be greater than 0"));
+        }
+
+        let segment_top_docs = vars.parse("SEGMENT_TOP_DOCS", "a number of hits");
+        if segment_top_docs == Some(0) {
+            vars.invalid(String::from("SEGMENT_TOP_DOCS must be greater than 0"));
+        }
+
+        let recency_half_life = vars.secs("RECENCY_HALF_LIFE_SECS").unwrap_or(Duration::from_secs(DEFAULT_RECENCY_HALF_LIFE_SECS));
+        if recency_half_life.is_zero() {
+            vars.invalid(String::from("RECENCY_HALF_LIFE_SECS must be greater than 0"));
+        }
+
+        let default_limit = vars.parse("DEFAULT_SEARCH_LIMIT", "a number of hits").unwrap_or(DEFAULT_SEARCH_LIMIT);
+        if default_limit == 0 || default_limit > MAX_SEARCH_LIMIT {
+            vars.invalid(format!("DEFAULT_SEARCH_LIMIT must be between 1 and {}, got: {}", MAX_SEARCH_LIMIT, default_limit));
+        }
+
+        let default_offset = vars.parse("DEFAULT_SEARCH_OFFSET", "a number of hits").unwrap_or(0);
+        if default_offset > MAX_SEARCH_OFFSET {
+            vars.invalid(format!("DEFAULT_SEARCH_OFFSET must be at most {}, got: {}", MAX_SEARCH_OFFSET, default_offset));
+        }
+
+        let expiry_sweep_interval = vars.secs("EXPIRY_SWEEP_INTERVAL_SECS").unwrap_or(Duration::from_secs(DEFAULT_EXPIRY_SWEEP_INTERVAL_SECS));
+        if expiry_sweep_interval.is_zero() {
+            vars.invalid(String::from("EXPIRY_SWEEP_INTERVAL_SECS must be greater than 0"));
+        }
+
+        let (default_reindex_url, default_backend_health_url) = match app_env.is_prod() {
+            true => ("http://app:8080/reindex", "http://app:8080/health"),
+            false => ("http://localhost:8080/reindex", "http://localhost:8080/health"),
+        };
+
+        let k1: Option<Score> = vars.parse("BM25_K1", "a number");
+        if let Some(k1) = k1.filter(|k1| *k1 < 0.0) {
+            vars.invalid(format!("BM25_K1 must be a non-negative number, got: {}", k1));
+        }
+        let b: Option<Score> = vars.parse("BM25_B", "a number");
+        if let Some(b) = b.filter(|b| !(0.0..=1.0).contains(b)) {
+            vars.invalid(format!("BM25_B must be between 0 and 1, got: {}", b));
+        }
+
+        IndexerConfig {
+            read_only: vars.flag("READ_ONLY").unwrap_or(false),
+            mode,
+            merge: MergeSettings {
+                min_num_segments: vars.parse("MERGE_MIN_NUM_SEGMENTS", "a number"),
+                min_layer_size: vars.parse("MERGE_MIN_LAYER_SIZE", "a number"),
+                max_docs_before_merge: vars.parse("MERGE_MAX_DOCS_BEFORE_MERGE", "a number"),
+            },
+            search_threads: vars.parse("SEARCH_THREADS", "a number"),
+            commit_every_docs,
+            commit_interval,
+            channel_capacity,
+            expiry_sweep_interval,
+            segment_top_docs,
+            recency_half_life,
+            default_limit,
+            default_offset,
+            writer_memory_bytes,
+            writer_threads,
+            store_compression,
+            reindex_url: vars.string("REINDEX_URL").unwrap_or_else(|| String::from(default_reindex_url)),
+            backend_health_url: Some(vars.string("BACKEND_HEALTH_URL").unwrap_or_else(|| String::from(default_backend_health_url))).filter(|url| !url.is_empty()),
+            max_token_len: vars.parse("MAX_TOKEN_LEN", "a number").unwrap_or(DEFAULT_MAX_TOKEN_LEN),
+            warmup_on_start: vars.flag("WARMUP_ON_START").unwrap_or(false),
+            search_timeout: vars.parse("SEARCH_TIMEOUT_MS", "a number of milliseconds").map(Duration::from_millis),
+            max_query_terms: vars.parse("MAX_QUERY_TERMS", "a number of terms"),
+            bm25: Bm25::new(k1, b),
+            query_cache_size: vars.parse::<usize>("QUERY_CACHE_SIZE", "a number").filter(|c| *c > 0),
+            query_cache_ttl: vars.secs("QUERY_CACHE_TTL_SECS").unwrap_or(Duration::from_secs(DEFAULT_QUERY_CACHE_TTL_SECS)),
+            field_boosts: vars.string("BOOSTS_FILE").map(|path| field_boosts(vars, &path)).unwrap_or_default(),
+        }
+    }
+}
+
+/// Only read at startup, changing the file needs a restart.
+fn field_boosts(vars: &mut EnvVars, path: &str) -> HashMap<String, HashMap<String, Score>> {
+    let parsed = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()));
+
+    match parsed {
+        Ok(field_boosts) => {
+            let field_boosts: HashMap<String, HashMap<String, Score>> = field_boosts;
+            if field_boosts.values().flat_map(|boosts| boosts.values()).any(|boost| *boost <= 0.0) {
+                vars.invalid(format!("BOOSTS_FILE boosts must be positive numbers, {} has others", path));
+            }
+            field_boosts
+        }
+        Err(e) => {
+            vars.invalid(format!("BOOSTS_FILE must be a JSON object of the field boosts per index, {} is not: {}", path, e));
+            HashMap::new()
+        }
+    }
+}
+
+/// The settings of a development server, whatever the environment says. Used by the tests.
+impl Default for IndexerConfig {
+    fn default() -> Self {
+        IndexerConfig {
+            read_only: false,
+            mode: IndexMode::OpenOrCreate,
+            merge: MergeSettings::default(),
+            search_threads: None,
+            commit_every_docs: None,
+            commit_interval: Duration::from_secs(DEFAULT_COMMIT_INTERVAL_SECS),
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            expiry_sweep_interval: Duration::from_secs(DEFAULT_EXPIRY_SWEEP_INTERVAL_SECS),
+            segment_top_docs: None,
+            recency_half_life: Duration::from_secs(DEFAULT_RECENCY_HALF_LIFE_SECS),
+            default_limit: DEFAULT_SEARCH_LIMIT,
+            default_offset: 0,
+            writer_memory_bytes: DEFAULT_WRITER_MEMORY_BYTES,
+            writer_threads: None,
+            store_compression: Compressor::default(),
+            reindex_url: String::from("http://localhost:8080/reindex"),
+            backend_health_url: None,
+            max_token_len: DEFAULT_MAX_TOKEN_LEN,
+            warmup_on_start: false,
+            search_timeout: None,
+            max_query_terms: None,
+            bm25: None,
+            query_cache_size: None,
+            query_cache_ttl: Duration::from_secs(DEFAULT_QUERY_CACHE_TTL_SECS),
+            field_boosts: HashMap::new(),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            cors_allowed_origins: String::new(),
+            index_api_key: None,
+            reindex_retry_after_secs: DEFAULT_REINDEX_RETRY_AFTER_SECS,
+            unified_weights: UnifiedWeights::default(),
+            max_concurrent_searches: None,
+            max_question_len: None,
+            lang_detection: true,
+            default_lang: None,
+        }
+    }
+}
+
+/// Reads the environment collecting a message for every invalid value.
+#[derive(Default)]
+struct EnvVars {
+    errors: Vec<String>,
+}
+
+impl EnvVars {
+    fn string(&self, name: &str) -> Option<String> {
+        env::var(name).ok()
+    }
+
+    fn parse<T: FromStr>(&mut self, name: &str, expected: &str) -> Option<T> {
+        let value = self.string(name)?;
+        match value.trim().parse() {
+            Ok(parsed) => Some(parsed),
+            Err(_) => {
+                self.invalid(format!("{} must be {}, got: {}", name, expected, value));
+                None
+            }
+        }
+    }
+
+    fn flag(&mut self, name: &str) -> Option<bool> {
+        let value = self.string(name)?;
+        match value.to_ascii_lowercase().as_str() {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => {
+                self.invalid(format!("{} must be true or false, got: {}", name, value));
+                None
+            }
+        }
+    }
+
+    fn secs(&mut self, name: &str) -> Option<Duration> {
+        self.parse(name, "a number of seconds").map(Duration::from_secs)
+    }
+
+    /// A non-negative score multiplier, 1.0 when unset.
+    fn weight(&mut self, name: &str) -> Score {
+        let weight: Score = self.parse(name, "a non-negative number").unwrap_or(1.0);
+        if weight < 0.0 {
+            self.invalid(format!("{} must be a non-negative number, got: {}", name, weight));
+        }
+
+        weight
+    }
+
+    fn invalid(&mut self, message: String) {
+        self.errors.push(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{EnvVars, field_boosts};
+
+    #[test]
+    fn it_should_collect_every_invalid_value() {
+        let mut vars = EnvVars::default();
+        std::env::set_var("CONFIG_TEST_NUMBER", "ten");
+        std::env::set_var("CONFIG_TEST_FLAG", "yes");
+        std::env::set_var("CONFIG_TEST_SECS", "5");
+
+        assert_eq!(vars.parse::<usize>("CONFIG_TEST_NUMBER", "a number"), None);
+        assert_eq!(vars.flag("CONFIG_TEST_FLAG"), None);
+        assert_eq!(vars.secs("CONFIG_TEST_SECS").map(|d| d.as_secs()), Some(5));
+        assert_eq!(vars.parse::<usize>("CONFIG_TEST_UNSET", "a number"), None);
+        assert_eq!(vars.errors, vec!["CONFIG_TEST_NUMBER must be a number, got: ten", "CONFIG_TEST_FLAG must be true or false, got: yes"]);
+    }
+
+    #[test]
+    fn it_should_read_the_field_boosts_of_every_index() {
+        let path = std::env::temp_dir().join("tantivy_search_boosts_test.json");
+        std::fs::write(&path, r#"{"questions": {"question": 2.0, "public_employment_name": 0.5}}"#).unwrap();
+        let mut vars = EnvVars::default();
+
+        let boosts = field_boosts(&mut vars, path.to_str().unwrap());
+        assert_eq!(boosts["questions"]["question"], 2.0);
+        assert_eq!(boosts["questions"]["public_employment_name"], 0.5);
+
+        std::fs::write(&path, r#"{"question": 2.0}"#).unwrap();
+        assert!(field_boosts(&mut vars, path.to_str().unwrap()).is_empty());
+        assert_eq!(vars.errors.len(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+}
\ No newline at end of file
@@ -0,0 +1,123 @@
+use std::{fmt, io};
+
+use axum::http::StatusCode;
+use axum::Json;
+use axum::response::{IntoResponse, Response};
+use serde_json::json;
+use tantivy::query::QueryParserError;
+use tantivy::TantivyError;
+
+/// Everything indexing or searching can fail with, each variant answered with its own HTTP
+/// status so every handler maps failures alike.
+#[derive(Clone, Debug)]
+pub enum SearchError {
+    /// The Go backend couldn't be asked to send the documents of an index again.
+    Reindex(String),
+    /// The index itself failed, mostly reading or writing its files, or a search timed out.
+    Io(TantivyError),
+    /// The schema lacks a field the operation needs, or doesn't match the one on disk.
+    Schema(String),
+    /// The client query can't be parsed or run, like a leading wildcard.
+    Query(String),
+    /// The actor of the named index stopped receiving messages.
+    ChannelClosed(String),
+    /// The named index is served read-only, it can't be changed.
+    ReadOnly(String),
+    /// As many searches as MAX_CONCURRENT_SEARCHES are already in flight.
+    Overloaded,
+}
+
+impl SearchError {
+    pub fn timeout(message: String) -> Self {
+        SearchError::Io(io::Error::new(io::ErrorKind::TimedOut, message).into())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            SearchError::Reindex(_) => StatusCode::BAD_GATEWAY,
+            SearchError::Io(TantivyError::IoError(e)) if e.kind() == io::ErrorKind::TimedOut => StatusCode::GATEWAY_TIMEOUT,
+            SearchError::Io(_) | SearchError::Schema(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            SearchError::Query(_) => StatusCode::BAD_REQUEST,
+            SearchError::ChannelClosed(_) | SearchError::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
+            SearchError::ReadOnly(_) => StatusCode::METHOD_NOT_ALLOWED,
+        }
+    }
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::Reindex(message) => write!(f, "reindex request failed: {}", message),
+            SearchError::Io(e) => write!(f, "{}", e),
+            SearchError::Schema(message) => write!(f, "schema error: {}", message),
+            SearchError::Query(message) => write!(f, "invalid query: {}", message),
+            SearchError::ChannelClosed(index_name) => write!(f, "{} index actor is not running", index_name),
+            SearchError::ReadOnly(index_name) => write!(f, "{} index is read-only", index_name),
+            SearchError::Overloaded => write!(f, "too many searches in flight"),
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+impl From<TantivyError> for SearchError {
+    fn from(e: TantivyError) -> Self {
+        match e {
+            TantivyError::InvalidArgument(message) => SearchError::Query(message),
+            TantivyError::SchemaError(message) | TantivyError::FieldNotFound(message) => SearchError::Schema(message),
+            e => SearchError::Io(e),
+        }
+    }
+}
+
+impl From<io::Error> for SearchError {
+    fn from(e: io::Error) -> Self {
+        SearchError::Io(e.into())
+    }
+}
+
+impl From<QueryParserError> for SearchError {
+    fn from(e: QueryParserError) -> Self {
+        SearchError::Query(format!("{:?}", e))
+    }
+}
+
+/// Client errors are only logged at debug level, server ones as errors.
+impl IntoResponse for SearchError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            tracing::error!("{:?}", self);
+        } else {
+            tracing::debug!("{:?}", self);
+        }
+
+        (status, Json(json!({ "error": self.to_string() }))).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use tantivy::TantivyError;
+
+    use crate::error::SearchError;
+
+    #[test]
+    fn it_should_answer_each_error_with_its_status() {
+        let errors = [
+            (SearchError::Reindex(String::from("connection refused")), StatusCode::BAD_GATEWAY),
+            (SearchError::timeout(String::from("questions search timed out")), StatusCode::GATEWAY_TIMEOUT),
+            (SearchError::from(TantivyError::Poisoned), StatusCode::INTERNAL_SERVER_ERROR),
+            (SearchError::from(TantivyError::FieldNotFound(String::from("id"))), StatusCode::INTERNAL_SERVER_ERROR),
+            (SearchError::from(TantivyError::InvalidArgument(String::from("*allo"))), StatusCode::BAD_REQUEST),
+            (SearchError::ChannelClosed(String::from("questions")), StatusCode::SERVICE_UNAVAILABLE),
+            (SearchError::ReadOnly(String::from("questions")), StatusCode::METHOD_NOT_ALLOWED),
+            (SearchError::Overloaded, StatusCode::SERVICE_UNAVAILABLE),
+        ];
+
+        for (error, status) in errors {
+            assert_eq!(error.status(), status, "{}", error);
+        }
+    }
+}
\ No newline at end of file
@@ -1,162 +1,700 @@
-use std::time::Duration;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use tantivy::{Directory, Document, Index, IndexSettings, IndexWriter, TantivyError, Term};
-use tantivy::schema::Schema;
-use tantivy::tokenizer::{AsciiFoldingFilter, Language, LowerCaser, RemoveLongFilter, SimpleTokenizer, Stemmer, StopWordFilter, TextAnalyzer};
-use tokio::sync::mpsc;
+use reqwest::StatusCode;
+use tantivy::{DocAddress, Directory, Document, Index, IndexReader, IndexSettings, IndexWriter, ReloadPolicy, Searcher, TantivyError, Term};
+use tantivy::collector::TopDocs;
+use tantivy::merge_policy::LogMergePolicy;
+use tantivy::query::{Query, RangeQuery, TermQuery};
+use tantivy::schema::{Field, FieldValue, IndexRecordOption, Schema, Value};
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, oneshot};
 use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::error::TrySendError;
 
-use crate::AppEnv;
+use crate::config::IndexerConfig;
+use crate::error::SearchError;
+use crate::indexation::tokenizer_manager;
 
 pub struct IndexActor {
     name: String,
     pub index: Index,
     schema: Schema,
     receiver: mpsc::Receiver<IndexActorMessage>,
-    writer: IndexWriter,
-    pub must_reindex: bool,
+    /// Ticks of the commit timer, received ahead of any queued message, see `commit_schedule`.
+    commits: Option<mpsc::Receiver<()>>,
+    /// `None` when the index is read-only.
+    writer: Option<IndexWriter>,
+    /// Only reloaded on demand to read the documents being partially updated.
+    reader: IndexReader,
+    /// Set while the index was erased for a schema change until the Go backend answered the
+    /// reindex request, shared with the handle.
+    pub must_reindex: Arc<AtomicBool>,
     must_commit: bool,
+    uncommitted_docs: usize,
+    commit_every_docs: Option<usize>,
+    /// Go backend endpoint asked for a reindex, the index name is appended to it.
+    reindex_url: String,
+    derive_fields: Option<fn(Document) -> Document>,
 }
 
 #[derive(Debug)]
 pub enum IndexActorMessage {
     Single { doc: Document },
     Commit,
+    /// Like `Commit`, answering the opstamp of the last commit once the pending changes are
+    /// committed.
+    Flush { respond_to: oneshot::Sender<Result<u64, SearchError>> },
     Delete { id: String },
-    Reindex { backend_env: AppEnv },
+    DeleteBatch { ids: Vec<String> },
+    /// Replaces the values of `fields` in the stored document with the ones in `doc`, answers
+    /// `false` when there is no document with that id.
+    Patch { id: String, fields: Vec<Field>, doc: Document, respond_to: oneshot::Sender<Result<bool, SearchError>> },
+    Reindex { respond_to: Option<oneshot::Sender<Result<StatusCode, SearchError>>> },
+    Merge { respond_to: oneshot::Sender<Result<usize, SearchError>> },
+    /// Deletes the documents whose `field` epoch millis are in the past.
+    DeleteExpired { field: Field },
 }
 
-pub fn run_index_actor(mut actor: IndexActor) {
-    while let Some(msg) = actor.receiver.blocking_recv() {
+/// How the index is opened at boot, taken from INDEX_MODE.
+///
+/// - `open_or_create`, the default: creates a missing index, and erases it to be reindexed
+///   when the schema changed.
+/// - `open`: the index must exist with the same schema, otherwise the actor fails to start
+///   so an operator can intervene before any data is lost.
+/// - `create`: always starts from an empty index that is reindexed.
+#[derive(Clone, Copy, Debug)]
+pub enum IndexMode {
+    OpenOrCreate,
+    Open,
+    Create,
+}
+
+/// Knobs of tantivy's `LogMergePolicy`, each one read from its own env var. When none is set
+/// the writer keeps tantivy's default merge policy.
+///
+/// - `MERGE_MIN_NUM_SEGMENTS`: minimum number of segments in a level to merge them.
+/// - `MERGE_MIN_LAYER_SIZE`: segments with less documents than this are all in the first level.
+/// - `MERGE_MAX_DOCS_BEFORE_MERGE`: segments with more documents than this are never merged.
+#[derive(Clone, Debug, Default)]
+pub struct MergeSettings {
+    pub min_num_segments: Option<usize>,
+    pub min_layer_size: Option<u32>,
+    pub max_docs_before_merge: Option<usize>,
+}
+
+impl MergeSettings {
+    pub fn merge_policy(&self) -> Option<LogMergePolicy> {
+        if self.min_num_segments.is_none() && self.min_layer_size.is_none() && self.max_docs_before_merge.is_none() {
+            return None;
+        }
+
+        let mut merge_policy = LogMergePolicy::default();
+        if let Some(min_num_segments) = self.min_num_segments {
+            merge_policy.set_min_num_segments(min_num_segments);
+        }
+        if let Some(min_layer_size) = self.min_layer_size {
+            merge_policy.set_min_layer_size(min_layer_size);
+        }
+        if let Some(max_docs_before_merge) = self.max_docs_before_merge {
+            merge_policy.set_max_docs_before_merge(max_docs_before_merge);
+        }
+
+        Some(merge_policy)
+    }
+}
+
+/// Deletes the expired documents every interval, they are gone from searches on the next commit.
+pub async fn run_expire_index(sender: Sender<IndexActorMessage>, index_name: String, field: Field, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+
+    loop {
+        interval.tick().await;
+        if sender.send(IndexActorMessage::DeleteExpired { field }).await.is_err() {
+            tracing::error!("{} index actor has been killed, stopping expiry sweeps", index_name);
+            break;
+        }
+    }
+}
+
+/// Runs on its own thread, only entering the runtime to wait for the next message so the
+/// blocking work of the messages, like the reindex requests, happens outside of it.
+pub fn run_index_actor(mut actor: IndexActor, runtime: Handle) {
+    while let Some(msg) = runtime.block_on(actor.next_message()) {
         if let Err(e) = actor.handle_message(msg) {
             tracing::error!("error while handling message in index actor: {:?}", e);
         }
     }
 }
 
-pub async fn run_commit_index(sender: Sender<IndexActorMessage>, index_name: String) {
-    let mut interval = tokio::time::interval(Duration::from_secs(30));
+/// Ticks a commit every interval. A tick still pending means the actor didn't commit since the
+/// last one, so it's not sent again.
+pub async fn run_commit_index(sender: Sender<()>, index_name: String, every: Duration) {
+    let mut interval = tokio::time::interval(every);
 
     loop {
         interval.tick().await;
-        sender
-            .send(IndexActorMessage::Commit)
-            .await
-            .unwrap_or_else(|_| panic!("{} index actor has been killed", index_name));
+        if let Err(TrySendError::Closed(_)) = sender.try_send(()) {
+            tracing::error!("{} index actor has been killed, stopping commits", index_name);
+            break;
+        }
     }
 }
 
 impl IndexActor {
-    pub fn new(name: String, dir: impl Directory, schema: Schema, receiver: mpsc::Receiver<IndexActorMessage>) -> Result<Self, TantivyError> {
-        let dir: Box<dyn Directory> = Box::new(dir);
-        let mut must_reindex = false;
-        let index = match Index::open_or_create(dir.clone(), schema.clone()) {
-            Ok(i) => i,
-            Err(e) => match e {
-                TantivyError::SchemaError(_) => {
-                    tracing::warn!("schema changed, erasing actual index and marking must_reindex flag");
-                    must_reindex = true;
-                    Index::create(dir.clone(), schema.clone(), IndexSettings::default())?
-                }
-                err => panic!("{:?}", err)
-            }
-        };
+    pub fn new(name: String, dir: impl Into<Box<dyn Directory>>, schema: Schema, schema_version: u32, receiver: mpsc::Receiver<IndexActorMessage>, config: &IndexerConfig, derive_fields: Option<fn(Document) -> Document>) -> Result<Self, SearchError> {
+        let dir: Box<dyn Directory> = dir.into();
+        // A read-only index can't be erased, it must already exist with the same schema
+        let mode = if config.read_only { IndexMode::Open } else { config.mode };
+        let settings = IndexSettings { docstore_compression: config.store_compression, ..IndexSettings::default() };
+        let (mut index, must_reindex) = open_index(&name, dir, &schema, schema_version, mode, settings)?;
+        if index.settings().docstore_compression != config.store_compression {
+            tracing::warn!("{} index stores its fields with {:?} rather than STORE_COMPRESSION {:?} until it's reindexed", name, index.settings().docstore_compression, config.store_compression);
+        }
 
-        index.tokenizers()
-            .register("ngram2", es_ngram2_analyzer());
+        index.set_tokenizers(tokenizer_manager(config.max_token_len));
+
+        // Collects the segments of a query in parallel, default is a single thread
+        if let Some(search_threads) = config.search_threads {
+            if search_threads > 1 {
+                index.set_multithread_executor(search_threads)?;
+            }
+        }
 
         // Should only be one writer at a time. This single IndexWriter is already
         // multithreaded.
-        let writer = index.writer(50_000_000)?;
+        let writer = match config.read_only {
+            true => None,
+            false => {
+                // Every thread takes an equal share of the budget, tantivy fails below 3MB a thread
+                let writer = match config.writer_threads {
+                    Some(num_threads) => index.writer_with_num_threads(num_threads, config.writer_memory_bytes)?,
+                    None => index.writer(config.writer_memory_bytes)?,
+                };
+                if let Some(merge_policy) = config.merge.merge_policy() {
+                    writer.set_merge_policy(Box::new(merge_policy));
+                }
+                Some(writer)
+            }
+        };
+        let reader = index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into()?;
 
         Ok(IndexActor {
             name,
             index,
             schema,
             receiver,
+            commits: None,
             writer,
-            must_reindex,
+            reader,
+            must_reindex: Arc::new(AtomicBool::new(must_reindex)),
             must_commit: false,
+            uncommitted_docs: 0,
+            // Commit as soon as this many documents changed instead of waiting for the commit timer
+            commit_every_docs: config.commit_every_docs,
+            reindex_url: config.reindex_url.clone(),
+            derive_fields,
         })
     }
 
-    fn handle_message(&mut self, msg: IndexActorMessage) -> Result<(), TantivyError> {
+    fn handle_message(&mut self, msg: IndexActorMessage) -> Result<(), SearchError> {
         match msg {
             IndexActorMessage::Single { doc } => {
                 if let Some(id_field) = self.schema.get_field("id") {
                     if let Some(id_value) = doc.get_first(id_field) {
                         if let Some(id) = id_value.as_text() {
-                            let str_id = String::from(id);
-                            let id_term = Term::from_field_text(id_field, id);
+                            let str_id = normalize_id(id);
+                            let id_term = Term::from_field_text(id_field, str_id.as_str());
+                            let doc = with_id(doc, id_field, str_id.as_str());
 
-                            self.writer.delete_term(id_term);
-                            self.writer.add_document(doc)?;
-                            self.must_commit = true;
+                            self.writer()?.delete_term(id_term);
+                            self.writer()?.add_document(doc)?;
                             tracing::info!("{} document with id: {} successfully indexed", &self.name, str_id);
+                            self.mark_for_commit(1)?;
 
                             Ok(())
                         } else {
-                            Err(TantivyError::FieldNotFound(String::from("id field value must be a string to index a single document")))
+                            Err(SearchError::Schema(String::from("id field value must be a string to index a single document")))
                         }
                     } else {
-                        Err(TantivyError::FieldNotFound(String::from("no id field found in single document while indexing")))
+                        Err(SearchError::Schema(String::from("no id field found in single document while indexing")))
                     }
                 } else {
-                    Err(TantivyError::FieldNotFound(String::from("no id field found in schema while indexing single document")))
+                    Err(SearchError::Schema(String::from("no id field found in schema while indexing single document")))
                 }
             }
-            IndexActorMessage::Commit => {
-                if self.must_commit {
-                    let opstamp = self.writer.commit()?;
-                    let index_name = &self.name;
-                    self.must_commit = false;
-                    tracing::info!("{index_name} documents committed successfully with opstamp: {opstamp}");
-                }
+            IndexActorMessage::Commit => self.commit().map(|_| ()),
+            IndexActorMessage::Flush { respond_to } => {
+                let result = self.commit();
+                let _ = respond_to.send(result.clone());
 
-                Ok(())
+                result.map(|_| ())
             }
             IndexActorMessage::Delete { id } => {
+                let id = normalize_id(id.as_str());
                 if let Some(id_field) = self.schema.get_field("id") {
                     let id_term = Term::from_field_text(id_field, id.as_str());
 
-                    self.writer.delete_term(id_term);
-                    self.must_commit = true;
+                    self.writer()?.delete_term(id_term);
                     tracing::info!("document {} successfully deleted", id);
+                    self.mark_for_commit(1)?;
 
                     Ok(())
                 } else {
-                    Err(TantivyError::FieldNotFound(format!("{} no id field found in schema while deleting document", id)))
+                    Err(SearchError::Schema(format!("{} no id field found in schema while deleting document", id)))
                 }
             }
-            IndexActorMessage::Reindex { backend_env } => {
-                let index_name = &self.name;
-                let mut go_backend_url = format!("http://localhost:8080/reindex/{}", index_name);
-                if backend_env.is_prod() {
-                    go_backend_url = format!("http://app:8080/reindex/{}", index_name);
+            IndexActorMessage::DeleteBatch { ids } => {
+                if let Some(id_field) = self.schema.get_field("id") {
+                    for id in &ids {
+                        self.writer()?.delete_term(Term::from_field_text(id_field, normalize_id(id).as_str()));
+                    }
+                    tracing::info!("{} documents successfully deleted", ids.len());
+                    self.mark_for_commit(ids.len())?;
+
+                    Ok(())
+                } else {
+                    Err(SearchError::Schema(String::from("no id field found in schema while deleting documents")))
                 }
+            }
+            IndexActorMessage::DeleteExpired { field } => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
 
-                match reqwest::blocking::get(go_backend_url) {
-                    Ok(r) => {
-                        if r.status().is_success() {
-                            self.must_reindex = false;
-                            tracing::info!("reindex triggered successfully");
-                            Ok(())
-                        } else {
-                            Err(TantivyError::SystemError(format!("{} HTTP error while reindexing", r.status())))
-                        }
-                    }
-                    Err(e) => Err(TantivyError::SystemError(format!("{:?}", e)))
+                let expired = RangeQuery::new_u64(field, 0..now);
+
+                // Only the committed documents are counted, uncommitted expired ones are deleted
+                // by the commit already pending for them
+                self.reader.reload()?;
+                let expired_docs = expired.count(&self.reader.searcher())?;
+                self.writer()?.delete_query(Box::new(expired))?;
+                if expired_docs == 0 {
+                    return Ok(());
                 }
+
+                tracing::debug!("{} documents of {} expired before {} deleted", expired_docs, &self.name, now);
+                self.mark_for_commit(expired_docs)
             }
+            IndexActorMessage::Patch { id, fields, doc, respond_to } => {
+                let result = self.patch(id.as_str(), &fields, doc);
+                let _ = respond_to.send(result.clone());
+
+                result.map(|_| ())
+            }
+            IndexActorMessage::Merge { respond_to } => {
+                let result = self.merge();
+                let _ = respond_to.send(result.clone());
+
+                result.map(|_| ())
+            }
+            IndexActorMessage::Reindex { respond_to } => {
+                let result = self.request_reindex();
+
+                if let Some(respond_to) = respond_to {
+                    let _ = respond_to.send(result.clone());
+                }
+
+                match result {
+                    Ok(status) if status.is_success() => Ok(()),
+                    Ok(status) => Err(SearchError::Reindex(format!("{} HTTP error while reindexing", status))),
+                    Err(e) => Err(e)
+                }
+            }
+        }
+    }
+
+    /// Channel of the commit timer, whose ticks are handled before any queued message so a
+    /// burst of documents can't delay the scheduled commits.
+    pub fn commit_schedule(&mut self) -> Sender<()> {
+        let (sender, commits) = mpsc::channel(1);
+        self.commits = Some(commits);
+
+        sender
+    }
+
+    /// `None` once every handle is dropped.
+    async fn next_message(&mut self) -> Option<IndexActorMessage> {
+        match &mut self.commits {
+            Some(commits) => tokio::select! {
+                biased;
+                Some(()) = commits.recv() => Some(IndexActorMessage::Commit),
+                msg = self.receiver.recv() => msg,
+            },
+            None => self.receiver.recv().await,
+        }
+    }
+
+    fn writer(&mut self) -> Result<&mut IndexWriter, SearchError> {
+        self.writer.as_mut().ok_or_else(|| SearchError::ReadOnly(self.name.clone()))
+    }
+
+    /// Returns the opstamp of the last commit, the previous one when nothing changed.
+    fn commit(&mut self) -> Result<u64, SearchError> {
+        if !self.must_commit {
+            return Ok(self.index.load_metas()?.opstamp);
+        }
+
+        let opstamp = self.writer()?.commit()?;
+        let index_name = &self.name;
+        self.must_commit = false;
+        self.uncommitted_docs = 0;
+        tracing::info!("{index_name} documents committed successfully with opstamp: {opstamp}");
+
+        Ok(opstamp)
+    }
+
+    /// Reads the stored document, overlays the patched fields and indexes it again. Pending
+    /// changes are committed first so the latest version of the document is the one read.
+    fn patch(&mut self, id: &str, fields: &[Field], patch: Document) -> Result<bool, SearchError> {
+        self.commit()?;
+        self.reader.reload()?;
+
+        let searcher = self.reader.searcher();
+        let doc_address = match find_doc_address(&searcher, id_term(&self.schema, id)?)? {
+            Some(doc_address) => doc_address,
+            None => return Ok(false),
+        };
+
+        let mut doc: Document = searcher.doc(doc_address)?
+            .into_iter()
+            .filter(|fv| !fields.contains(&fv.field()))
+            .collect::<Vec<FieldValue>>()
+            .into();
+        for fv in patch {
+            doc.add_field_value(fv.field(), fv.value().clone());
+        }
+        if let Some(derive_fields) = self.derive_fields {
+            doc = derive_fields(doc);
+        }
+
+        self.handle_message(IndexActorMessage::Single { doc })?;
+
+        Ok(true)
+    }
+
+    /// Merges every searchable segment into a single one, returning the resulting segment count.
+    fn merge(&mut self) -> Result<usize, SearchError> {
+        self.commit()?;
+
+        let segment_ids = self.index.searchable_segment_ids()?;
+        if segment_ids.len() > 1 {
+            self.writer()?.merge(&segment_ids).wait()?;
+            self.must_commit = true;
+            self.commit()?;
+            tracing::info!("{} merged {} segments", &self.name, segment_ids.len());
+        }
+
+        Ok(self.index.searchable_segment_ids()?.len())
+    }
+
+    /// Whichever comes first, the commit timer or reaching `commit_every_docs` changed documents
+    /// triggers the commit.
+    fn mark_for_commit(&mut self, changed_docs: usize) -> Result<(), SearchError> {
+        self.must_commit = true;
+        self.uncommitted_docs += changed_docs;
+
+        match self.commit_every_docs {
+            Some(max_docs) if self.uncommitted_docs >= max_docs => self.commit().map(|_| ()),
+            _ => Ok(())
+        }
+    }
+
+    /// Asks the Go backend to send every document of this index again. Any HTTP response
+    /// is returned as is, only connection errors are mapped to a `SearchError`.
+    fn request_reindex(&mut self) -> Result<StatusCode, SearchError> {
+        let go_backend_url = format!("{}/{}", self.reindex_url.trim_end_matches('/'), self.name);
+
+        match reqwest::blocking::get(go_backend_url) {
+            Ok(r) => {
+                if r.status().is_success() {
+                    self.must_reindex.store(false, Ordering::Relaxed);
+                    tracing::info!("reindex triggered successfully");
+                }
+
+                Ok(r.status())
+            }
+            Err(e) => Err(SearchError::Reindex(format!("{:?}", e)))
         }
     }
 }
 
-fn es_ngram2_analyzer() -> TextAnalyzer {
-    TextAnalyzer::from(SimpleTokenizer)
-        .filter(RemoveLongFilter::limit(40))
-        .filter(LowerCaser)
-        .filter(AsciiFoldingFilter) // remove accents
-        .filter(StopWordFilter::new(Language::Spanish).unwrap())
-        .filter(Stemmer::new(Language::Spanish))
+/// File next to the index files with the schema version the index was built with.
+const SCHEMA_VERSION_FILE: &str = "schema_version";
+
+/// Opens the index as the mode says, also telling whether it was erased and must be reindexed.
+/// Besides tantivy's schema check, an index built with another schema version is erased too, so
+/// intentional schema bumps are told apart from indexes changed without one or corrupted.
+/// The settings only apply to the indexes created here, an opened one keeps the settings it was
+/// created with.
+fn open_index(name: &str, dir: Box<dyn Directory>, schema: &Schema, schema_version: u32, mode: IndexMode, settings: IndexSettings) -> Result<(Index, bool), SearchError> {
+    // Missing for the indexes built before versioning them, which are taken as up to date
+    let disk_version = read_schema_version(dir.as_ref());
+    let mut must_reindex = false;
+    let index = match mode {
+        IndexMode::OpenOrCreate => match Index::builder().schema(schema.clone()).settings(settings.clone()).open_or_create(dir.clone()) {
+            Ok(index) if disk_version.unwrap_or(schema_version) == schema_version => index,
+            Ok(_) => {
+                tracing::warn!("{} index schema version bumped from {:?} to {}, erasing actual index and marking must_reindex flag", name, disk_version, schema_version);
+                must_reindex = true;
+                Index::create(dir.clone(), schema.clone(), settings.clone())?
+            }
+            Err(TantivyError::SchemaError(_)) => {
+                match disk_version {
+                    Some(version) if version == schema_version => tracing::error!("{} index schema differs from the one of its version {}, changed without a version bump or corrupted, erasing actual index and marking must_reindex flag", name, version),
+                    Some(version) => tracing::warn!("{} index schema version bumped from {} to {}, erasing actual index and marking must_reindex flag", name, version, schema_version),
+                    None => tracing::warn!("schema changed, erasing actual index and marking must_reindex flag"),
+                }
+                must_reindex = true;
+                Index::create(dir.clone(), schema.clone(), settings.clone())?
+            }
+            Err(err) => panic!("{:?}", err)
+        },
+        IndexMode::Open => {
+            let index = Index::open(dir.clone())?;
+            if index.schema() != *schema {
+                return Err(SearchError::Schema(format!("{} index schema changed, refusing to erase it with INDEX_MODE=open", name)));
+            }
+            if let Some(version) = disk_version.filter(|version| *version != schema_version) {
+                return Err(SearchError::Schema(format!("{} index has schema version {} instead of {}, refusing to erase it with INDEX_MODE=open", name, version, schema_version)));
+            }
+            // Read-only indexes are opened this way, so nothing is written
+            return Ok((index, false));
+        }
+        IndexMode::Create => {
+            tracing::warn!("{} index erased with INDEX_MODE=create, marking must_reindex flag", name);
+            must_reindex = true;
+            Index::create(dir.clone(), schema.clone(), settings.clone())?
+        }
+    };
+
+    dir.atomic_write(Path::new(SCHEMA_VERSION_FILE), schema_version.to_string().as_bytes())?;
+
+    Ok((index, must_reindex))
+}
+
+/// Whether opening the index as the mode says erases it, mirroring `open_index` without
+/// changing anything.
+pub fn must_erase(dir: &dyn Directory, schema: &Schema, schema_version: u32, mode: IndexMode) -> bool {
+    match mode {
+        IndexMode::Create => true,
+        IndexMode::Open => false,
+        IndexMode::OpenOrCreate => match Index::exists(dir) {
+            Ok(true) => match Index::open(dir.box_clone()) {
+                Ok(index) => index.schema() != *schema || read_schema_version(dir).unwrap_or(schema_version) != schema_version,
+                Err(_) => false,
+            },
+            _ => false,
+        },
+    }
+}
+
+/// `None` when there is no version file, or it can't be read.
+fn read_schema_version(dir: &dyn Directory) -> Option<u32> {
+    dir.atomic_read(Path::new(SCHEMA_VERSION_FILE))
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .and_then(|version| version.trim().parse().ok())
+}
+
+/// Ids are indexed untokenized, so term lookups are case-sensitive. Every id is stored and
+/// looked up in lowercase so clients sending the same id with another casing still match.
+pub fn normalize_id(id: &str) -> String {
+    id.to_lowercase()
+}
+
+pub fn id_term(schema: &Schema, id: &str) -> Result<Term, SearchError> {
+    let id_field = schema
+        .get_field("id")
+        .ok_or_else(|| SearchError::Schema(String::from("no id field found in schema while looking up a document by id")))?;
+
+    Ok(Term::from_field_text(id_field, normalize_id(id).as_str()))
+}
+
+/// Resolves an id term to the address of its document in the given searcher.
+pub fn find_doc_address(searcher: &Searcher, id_term: Term) -> Result<Option<DocAddress>, SearchError> {
+    let query = TermQuery::new(id_term, IndexRecordOption::Basic);
+    let top_docs = searcher.search(&query, &TopDocs::with_limit(1))?;
+
+    Ok(top_docs.first().map(|(_, doc_address)| *doc_address))
+}
+
+fn with_id(doc: Document, id_field: Field, id: &str) -> Document {
+    doc.into_iter()
+        .map(|fv| if fv.field() == id_field { FieldValue::new(id_field, Value::Str(String::from(id))) } else { fv })
+        .collect::<Vec<FieldValue>>()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use tantivy::{doc, IndexSettings, Term};
+    use tantivy::store::{Compressor, ZstdCompressor};
+    use tantivy::directory::RamDirectory;
+    use tokio::sync::mpsc;
+
+    use crate::config::IndexerConfig;
+    use crate::error::SearchError;
+    use crate::indexation::actor::{find_doc_address, IndexActor, IndexActorMessage, IndexMode, MergeSettings, must_erase, open_index, read_schema_version};
+    use crate::person::new_person_schema;
+    use crate::question::new_question_schema;
+
+    #[test]
+    fn it_should_only_apply_the_store_compression_to_created_indexes() {
+        let dir = RamDirectory::create();
+        let config = IndexerConfig { store_compression: Compressor::Zstd(ZstdCompressor::default()), ..IndexerConfig::default() };
+        let (_sender, receiver) = mpsc::channel(1);
+        let actor = IndexActor::new(String::from("test"), dir.clone(), new_question_schema(), 1, receiver, &config, None).unwrap();
+        assert_eq!(actor.index.settings().docstore_compression, config.store_compression);
+        drop(actor);
+
+        let (_sender, receiver) = mpsc::channel(1);
+        let actor = IndexActor::new(String::from("test"), dir, new_question_schema(), 1, receiver, &IndexerConfig::default(), None).unwrap();
+        assert_eq!(actor.index.settings().docstore_compression, config.store_compression);
+    }
+
+    #[test]
+    fn it_should_refuse_to_erase_a_changed_schema_in_open_mode() {
+        let dir = RamDirectory::create();
+        open_index("test", Box::new(dir.clone()), &new_person_schema(), 1, IndexMode::Create, IndexSettings::default()).unwrap();
+
+        let result = open_index("test", Box::new(dir.clone()), &new_question_schema(), 1, IndexMode::Open, IndexSettings::default());
+        assert!(matches!(result, Err(SearchError::Schema(_))));
+
+        let (_, must_reindex) = open_index("test", Box::new(dir), &new_question_schema(), 1, IndexMode::OpenOrCreate, IndexSettings::default()).unwrap();
+        assert!(must_reindex);
+    }
+
+    #[test]
+    fn it_should_erase_an_index_built_with_another_schema_version() {
+        let dir = RamDirectory::create();
+        let (_, must_reindex) = open_index("test", Box::new(dir.clone()), &new_question_schema(), 1, IndexMode::OpenOrCreate, IndexSettings::default()).unwrap();
+        assert!(!must_reindex);
+
+        let (_, must_reindex) = open_index("test", Box::new(dir.clone()), &new_question_schema(), 1, IndexMode::OpenOrCreate, IndexSettings::default()).unwrap();
+        assert!(!must_reindex);
+        assert!(!must_erase(&dir, &new_question_schema(), 1, IndexMode::OpenOrCreate));
+        assert!(must_erase(&dir, &new_question_schema(), 2, IndexMode::OpenOrCreate));
+        assert!(must_erase(&dir, &new_person_schema(), 1, IndexMode::OpenOrCreate));
+
+        let result = open_index("test", Box::new(dir.clone()), &new_question_schema(), 2, IndexMode::Open, IndexSettings::default());
+        assert!(matches!(result, Err(SearchError::Schema(_))));
+
+        let (_, must_reindex) = open_index("test", Box::new(dir.clone()), &new_question_schema(), 2, IndexMode::OpenOrCreate, IndexSettings::default()).unwrap();
+        assert!(must_reindex);
+        assert_eq!(read_schema_version(&dir), Some(2));
+    }
+
+    #[test]
+    fn it_should_index_with_the_given_writer_threads() {
+        let (_sender, receiver) = mpsc::channel(8);
+        let config = IndexerConfig { writer_threads: Some(3), ..IndexerConfig::default() };
+        let mut actor = IndexActor::new(String::from("test"), RamDirectory::create(), new_question_schema(), 1, receiver, &config, None).unwrap();
+        let id_field = actor.schema.get_field("id").unwrap();
+
+        for id in 0..30 {
+            actor.handle_message(IndexActorMessage::Single { doc: doc!(id_field => id.to_string()) }).unwrap();
+        }
+        actor.handle_message(IndexActorMessage::Commit).unwrap();
+        actor.reader.reload().unwrap();
+
+        let searcher = actor.reader.searcher();
+        assert_eq!(searcher.num_docs(), 30);
+        assert!(searcher.segment_readers().len() <= 3);
+    }
+
+    #[test]
+    fn it_should_delete_the_expired_documents() {
+        let (_sender, receiver) = mpsc::channel(8);
+        let mut actor = IndexActor::new(String::from("test"), RamDirectory::create(), new_question_schema(), 1, receiver, &IndexerConfig::default(), None).unwrap();
+        let id_field = actor.schema.get_field("id").unwrap();
+        let expires_at = actor.schema.get_field("expires_at").unwrap();
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        for doc in [doc!(id_field => "1", expires_at => now - 1000), doc!(id_field => "2", expires_at => now + 60_000), doc!(id_field => "3")] {
+            actor.handle_message(IndexActorMessage::Single { doc }).unwrap();
+        }
+        actor.handle_message(IndexActorMessage::DeleteExpired { field: expires_at }).unwrap();
+        actor.handle_message(IndexActorMessage::Commit).unwrap();
+        actor.reader.reload().unwrap();
+
+        let searcher = actor.reader.searcher();
+        assert_eq!(searcher.num_docs(), 2);
+        for (id, found) in [("1", false), ("2", true), ("3", true)] {
+            assert_eq!(find_doc_address(&searcher, Term::from_field_text(id_field, id)).unwrap().is_some(), found, "document {}", id);
+        }
+
+        // Nothing left to expire, nothing to commit
+        actor.handle_message(IndexActorMessage::DeleteExpired { field: expires_at }).unwrap();
+        assert!(!actor.must_commit);
+    }
+
+    #[tokio::test]
+    async fn it_should_receive_the_scheduled_commits_before_the_queued_messages() {
+        let (sender, receiver) = mpsc::channel(8);
+        let mut actor = IndexActor::new(String::from("test"), RamDirectory::create(), new_question_schema(), 1, receiver, &IndexerConfig::default(), None).unwrap();
+        let commits = actor.commit_schedule();
+
+        let id_field = actor.schema.get_field("id").unwrap();
+        for i in 0..3 {
+            sender.send(IndexActorMessage::Single { doc: doc!(id_field => i.to_string()) }).await.unwrap();
+        }
+        commits.try_send(()).unwrap();
+        assert!(commits.try_send(()).is_err());
+
+        assert!(matches!(actor.next_message().await, Some(IndexActorMessage::Commit)));
+        assert!(matches!(actor.next_message().await, Some(IndexActorMessage::Single { .. })));
+
+        drop(commits);
+        drop(sender);
+        assert!(matches!(actor.next_message().await, Some(IndexActorMessage::Single { .. })));
+        assert!(matches!(actor.next_message().await, Some(IndexActorMessage::Single { .. })));
+        assert!(actor.next_message().await.is_none());
+    }
+
+    #[test]
+    fn it_should_commit_every_configured_number_of_docs() {
+        let (_sender, receiver) = mpsc::channel(8);
+        let config = IndexerConfig { commit_every_docs: Some(3), ..IndexerConfig::default() };
+        let mut actor = IndexActor::new(String::from("test"), RamDirectory::create(), new_question_schema(), 1, receiver, &config, None).unwrap();
+
+        let id_field = actor.schema.get_field("id").unwrap();
+        for i in 0..2 {
+            actor.handle_message(IndexActorMessage::Single { doc: doc!(id_field => i.to_string()) }).unwrap();
+        }
+        assert_eq!(actor.index.load_metas().unwrap().opstamp, 0);
+        assert_eq!(actor.uncommitted_docs, 2);
+
+        actor.handle_message(IndexActorMessage::Single { doc: doc!(id_field => String::from("2")) }).unwrap();
+        assert!(actor.index.load_metas().unwrap().opstamp > 0);
+        assert_eq!(actor.uncommitted_docs, 0);
+        assert!(!actor.must_commit);
+    }
+
+    #[test]
+    fn it_should_merge_segments_with_the_configured_merge_policy() {
+        let (_sender, receiver) = mpsc::channel(8);
+        let config = IndexerConfig { merge: MergeSettings { min_num_segments: Some(2), ..Default::default() }, ..IndexerConfig::default() };
+        let mut actor = IndexActor::new(String::from("test"), RamDirectory::create(), new_question_schema(), 1, receiver, &config, None).unwrap();
+
+        let id_field = actor.schema.get_field("id").unwrap();
+        for i in 0..8 {
+            actor.handle_message(IndexActorMessage::Single { doc: doc!(id_field => i.to_string()) }).unwrap();
+            actor.handle_message(IndexActorMessage::Commit).unwrap();
+        }
+
+        // Merged in the background, waited for at most 5 seconds
+        let mut segments = actor.index.searchable_segment_ids().unwrap().len();
+        for _ in 0..500 {
+            if segments == 1 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+            segments = actor.index.searchable_segment_ids().unwrap().len();
+        }
+
+        assert_eq!(segments, 1);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::indexation::handle::SearchDocument;
+
+/// Results of the latest searches, at most QUERY_CACHE_SIZE of them evicting the least recently
+/// used, each one for QUERY_CACHE_TTL_SECS. Every entry remembers the searcher generation it was
+/// found with, so the reader reloading after a commit busts all of them.
+pub struct QueryCache {
+    capacity: usize,
+    ttl: Duration,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, CacheEntry>,
+    /// Incremented on every access, the entry with the lowest `last_used` is the next evicted.
+    clock: u64,
+}
+
+struct CacheEntry {
+    docs: Vec<SearchDocument>,
+    generation: u64,
+    inserted_at: Instant,
+    last_used: u64,
+}
+
+impl QueryCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        QueryCache { capacity, ttl, state: Mutex::new(CacheState::default()) }
+    }
+
+    pub fn get(&self, key: &str, generation: u64) -> Option<Vec<SearchDocument>> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+
+        let fresh = match state.entries.get(key) {
+            Some(entry) => entry.generation == generation && entry.inserted_at.elapsed() < self.ttl,
+            None => return None,
+        };
+        if !fresh {
+            state.entries.remove(key);
+            return None;
+        }
+
+        let entry = state.entries.get_mut(key)?;
+        entry.last_used = clock;
+        Some(entry.docs.clone())
+    }
+
+    pub fn insert(&self, key: String, generation: u64, docs: Vec<SearchDocument>) {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let last_used = state.clock;
+
+        if !state.entries.contains_key(&key) && state.entries.len() >= self.capacity {
+            // A linear scan, capacities are small enough for it to be cheaper than the search
+            let evicted = state.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(key, _)| key.clone());
+            if let Some(evicted) = evicted {
+                state.entries.remove(&evicted);
+            }
+        }
+
+        state.entries.insert(key, CacheEntry { docs, generation, inserted_at: Instant::now(), last_used });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use tantivy::Document;
+
+    use crate::indexation::cache::QueryCache;
+    use crate::indexation::handle::SearchDocument;
+
+    fn docs(score: f32) -> Vec<SearchDocument> {
+        vec![SearchDocument { doc: Document::new(), score, snippets: BTreeMap::new(), doc_address: None }]
+    }
+
+    #[test]
+    fn it_should_serve_the_same_generation_until_a_commit() {
+        let cache = QueryCache::new(4, Duration::from_secs(60));
+        cache.insert(String::from("caballo"), 1, docs(1.0));
+
+        assert_eq!(cache.get("caballo", 1).unwrap()[0].score, 1.0);
+        assert!(cache.get("caballo", 2).is_none());
+        // Busted entries are dropped, an older reader doesn't bring them back
+        assert!(cache.get("caballo", 1).is_none());
+    }
+
+    #[test]
+    fn it_should_expire_entries_after_the_ttl() {
+        let cache = QueryCache::new(4, Duration::ZERO);
+        cache.insert(String::from("caballo"), 1, docs(1.0));
+
+        assert!(cache.get("caballo", 1).is_none());
+    }
+
+    #[test]
+    fn it_should_evict_the_least_recently_used_entry() {
+        let cache = QueryCache::new(2, Duration::from_secs(60));
+        cache.insert(String::from("caballo"), 1, docs(1.0));
+        cache.insert(String::from("blanco"), 1, docs(2.0));
+        cache.get("caballo", 1);
+        cache.insert(String::from("negro"), 1, docs(3.0));
+
+        assert!(cache.get("caballo", 1).is_some());
+        assert!(cache.get("blanco", 1).is_none());
+        assert!(cache.get("negro", 1).is_some());
+    }
+}
\ No newline at end of file
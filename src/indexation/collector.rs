@@ -0,0 +1,81 @@
+use std::cmp::Ordering;
+
+use tantivy::{DocAddress, SegmentOrdinal, SegmentReader};
+use tantivy::collector::{Collector, SegmentCollector};
+
+/// Keeps the best `limit` hits of a top docs collector built with a lower limit, which then
+/// only bounds the hits kept per segment. Each segment allocates for its own limit only, but a
+/// segment holding more of the best hits than it keeps loses the rest, which the next ranked
+/// hits replace. With the same limit it collects exactly like the wrapped collector.
+pub struct SegmentLimited<C> {
+    inner: C,
+    limit: usize,
+}
+
+impl<C> SegmentLimited<C> {
+    pub fn new(inner: C, limit: usize) -> Self {
+        SegmentLimited { inner, limit }
+    }
+}
+
+impl<C, T> Collector for SegmentLimited<C>
+    where C: Collector<Fruit=Vec<(T, DocAddress)>>,
+          T: PartialOrd + Send + 'static {
+    type Fruit = Vec<(T, DocAddress)>;
+    type Child = C::Child;
+
+    fn for_segment(&self, segment_local_id: SegmentOrdinal, segment: &SegmentReader) -> tantivy::Result<Self::Child> {
+        self.inner.for_segment(segment_local_id, segment)
+    }
+
+    fn requires_scoring(&self) -> bool {
+        self.inner.requires_scoring()
+    }
+
+    /// The hits of each segment go through the wrapped collector alone, which only sorts them as its
+    /// limit already bounds them. Segments come in order, so the stable sort then breaks ties by
+    /// address like tantivy does.
+    fn merge_fruits(&self, segment_fruits: Vec<<Self::Child as SegmentCollector>::Fruit>) -> tantivy::Result<Self::Fruit> {
+        let mut hits: Vec<(T, DocAddress)> = Vec::new();
+        for segment_fruit in segment_fruits {
+            hits.extend(self.inner.merge_fruits(vec![segment_fruit])?);
+        }
+        hits.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+        hits.truncate(self.limit);
+
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tantivy::{doc, Index};
+    use tantivy::collector::TopDocs;
+    use tantivy::query::AllQuery;
+    use tantivy::schema::{FAST, Schema};
+
+    use crate::indexation::collector::SegmentLimited;
+
+    #[test]
+    fn it_should_only_keep_the_segment_limit_of_every_segment() {
+        let mut schema_builder = Schema::builder();
+        let rank = schema_builder.add_u64_field("rank", FAST);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        // The three best hits are in the first segment
+        for segment in [[10, 9, 8], [7, 1, 0]] {
+            for value in segment {
+                writer.add_document(doc!(rank => value as u64)).unwrap();
+            }
+            writer.commit().unwrap();
+        }
+        let searcher = index.reader().unwrap().searcher();
+
+        let ranks = |segment_limit: usize| -> Vec<u64> {
+            let collector = SegmentLimited::new(TopDocs::with_limit(segment_limit).order_by_u64_field(rank), 3);
+            searcher.search(&AllQuery, &collector).unwrap().into_iter().map(|(rank, _)| rank).collect()
+        };
+        assert_eq!(ranks(3), vec![10, 9, 8]);
+        assert_eq!(ranks(2), vec![10, 9, 7]);
+    }
+}
@@ -0,0 +1,68 @@
+use std::iter::once;
+
+use tantivy::tokenizer::{BoxTokenStream, Token, TokenFilter, TokenStream};
+
+/// Emits every prefix of each token with at least `min_gram` characters, the whole token
+/// included, at the position of the token so phrases keep matching. Only meant for indexing,
+/// queries must be analyzed without it to search the prefix they were given.
+#[derive(Clone)]
+pub struct EdgeNgramFilter {
+    min_gram: usize,
+}
+
+impl EdgeNgramFilter {
+    pub fn new(min_gram: usize) -> Self {
+        EdgeNgramFilter { min_gram: min_gram.max(1) }
+    }
+}
+
+impl TokenFilter for EdgeNgramFilter {
+    fn transform<'a>(&self, token_stream: BoxTokenStream<'a>) -> BoxTokenStream<'a> {
+        BoxTokenStream::from(EdgeNgramTokenStream { tail: token_stream, min_gram: self.min_gram, token: Token::default(), ends: Vec::new() })
+    }
+}
+
+pub struct EdgeNgramTokenStream<'a> {
+    tail: BoxTokenStream<'a>,
+    min_gram: usize,
+    token: Token,
+    /// Byte lengths of the prefixes of the current tail token still to emit, the shortest last.
+    ends: Vec<usize>,
+}
+
+impl<'a> TokenStream for EdgeNgramTokenStream<'a> {
+    fn advance(&mut self) -> bool {
+        loop {
+            if let Some(end) = self.ends.pop() {
+                let source = self.tail.token();
+                self.token.text.clear();
+                self.token.text.push_str(&source.text[..end]);
+                self.token.offset_from = source.offset_from;
+                self.token.offset_to = (source.offset_from + end).min(source.offset_to);
+                self.token.position = source.position;
+                self.token.position_length = source.position_length;
+                return true;
+            }
+            if !self.tail.advance() {
+                return false;
+            }
+
+            // Tokens shorter than `min_gram` are still emitted whole
+            let text = &self.tail.token().text;
+            self.ends = text.char_indices()
+                .map(|(index, _)| index)
+                .skip(self.min_gram)
+                .chain(once(text.len()))
+                .collect();
+            self.ends.reverse();
+        }
+    }
+
+    fn token(&self) -> &Token {
+        &self.token
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.token
+    }
+}
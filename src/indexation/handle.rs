@@ -1,35 +1,201 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::thread;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use tantivy::{Directory, Document, IndexReader, ReloadPolicy, Score, TantivyError};
-use tantivy::collector::TopDocs;
-use tantivy::query::QueryParser;
-use tantivy::schema::Schema;
-use tokio::sync::mpsc;
+use reqwest::StatusCode;
+use tantivy::{DocAddress, DocId, Directory, Document, IndexReader, ReloadPolicy, Score, Searcher, SegmentReader, TantivyError, Term};
+use tantivy::collector::{Count, FacetCollector, TopDocs};
+use tantivy::query::{AllQuery, BooleanQuery, BoostQuery, Explanation, FuzzyTermQuery, Occur, Query, QueryParser, RegexQuery, TermQuery};
+use tantivy::schema::{Field, FieldType, FieldValue, IndexRecordOption, Schema};
+use tantivy::SnippetGenerator;
+use tantivy::tokenizer::{TextAnalyzer, TokenizerManager};
+use tokio::sync::{mpsc, oneshot};
 
-use crate::AppEnv;
-use crate::indexation::actor::{IndexActor, IndexActorMessage, run_commit_index, run_index_actor};
+use crate::config::IndexerConfig;
+use crate::error::SearchError;
+use crate::indexation::{field_to_string, IndexDefinition, search_tokenizer_manager};
+use crate::indexation::actor::{find_doc_address, id_term, IndexActor, IndexActorMessage, must_erase, run_commit_index, run_expire_index, run_index_actor};
+use crate::indexation::cache::QueryCache;
+use crate::indexation::collector::SegmentLimited;
+use crate::indexation::scoring::{Bm25, Recency, ScoreBoost};
+
+/// Documents retrieved ahead of the client reading a streamed search.
+const STREAM_BUFFER: usize = 64;
+/// The Go backend must answer its health check within this time before an index is erased.
+const BACKEND_HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Shorter autocomplete prefixes are only expanded, a typo in them would match most terms.
+const MIN_FUZZY_PREFIX_LEN: usize = 3;
+/// Hits retrieved per autocomplete suggestion, the duplicated texts are dropped among them.
+const SUGGESTION_CANDIDATES: usize = 4;
+/// Hits tied past the limit of a sorted search retrieved at most to break the ties, beyond
+/// them the tied hits keep the index order.
+const MAX_TIED_HITS: usize = 1000;
+/// Hits a search can ask for at most, whatever its `limit`.
+pub const MAX_SEARCH_LIMIT: usize = 100;
+/// Best hits a search can skip at most, they are all retrieved to be skipped.
+pub const MAX_SEARCH_OFFSET: usize = 10_000;
 
 #[derive(Clone)]
 pub struct IndexActorHandle {
+    index_name: String,
     sender: mpsc::Sender<IndexActorMessage>,
     reader: IndexReader,
     query_parser: QueryParser,
+    /// Searched by prefix terms without a field, like the query parser default fields.
+    default_fields: Vec<Field>,
+    tokenizers: TokenizerManager,
+    schema: Schema,
+    /// Searches running longer are abandoned, read from `SEARCH_TIMEOUT_MS`.
+    search_timeout: Option<Duration>,
+    bm25: Option<Bm25>,
+    /// Sort of the searches browsing the index with an empty query.
+    default_sort: Option<Sort>,
+    tie_breaker: Option<Field>,
+    segment_top_docs: Option<usize>,
+    /// Age halving the relevance of the searches boosting recent hits, read from
+    /// `RECENCY_HALF_LIFE_SECS`.
+    recency_half_life: Duration,
+    /// Paging of the searches not asking for their own, read from `DEFAULT_SEARCH_LIMIT` and
+    /// `DEFAULT_SEARCH_OFFSET`.
+    default_limit: usize,
+    default_offset: usize,
+    query_cache: Option<Arc<QueryCache>>,
+    /// Queries analyzed into more terms are rejected, read from `MAX_QUERY_TERMS`.
+    max_query_terms: Option<usize>,
+    /// Every message changing the index is refused, read from `READ_ONLY`.
+    read_only: bool,
+    /// Multiplies the score of the matches of each field, read from `BOOSTS_FILE`.
+    field_boosts: Vec<(Field, Score)>,
+    reindexing: Arc<AtomicBool>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchOptions {
+    /// u64 fast field whose log2 multiplies the text relevance of every hit.
+    pub boost_field: Option<Field>,
+    /// u64 fast field with epoch millis, the text relevance of every hit halved for every
+    /// RECENCY_HALF_LIFE_SECS they're older than the search.
+    pub recency_field: Option<Field>,
+    /// Exact terms every hit must contain, on top of the parsed query.
+    pub filters: Vec<Term>,
+    /// Stored fields kept in every hit, all of them by default. Tantivy 0.19 decompresses the
+    /// whole doc store block and deserializes every field of a hit no matter what, so this
+    /// can't make the retrieval itself cheaper, it only frees the unneeded values, like a
+    /// large `metadata`, before they're mapped to the response.
+    pub stored_fields: Option<Vec<Field>>,
+    /// Hits scoring less are dropped. The limit is applied before this filter, so fewer hits
+    /// than the limit may be returned even when more documents match.
+    pub min_score: Option<Score>,
+    /// Orders hits by a u64 fast field instead of relevance, ties still ranked by relevance.
+    pub sort: Option<Sort>,
+    /// Fields searched by unqualified terms instead of the index default ones.
+    pub default_fields: Option<Vec<Field>>,
+    /// Precomputed u64 fast field ordering the hits instead of relevance, which is then never
+    /// computed, and reported as their score. Much faster on large indexes, but approximate: the
+    /// best ranked matches come first no matter how well they match the query. Tantivy 0.19
+    /// still visits every match, it only skips scoring them. Takes precedence over `sort`,
+    /// `boost_field`, `recency_field` and custom BM25 parameters.
+    pub rank_field: Option<Field>,
+    /// Highlights the query terms found in the given fields of every hit, none by default.
+    pub snippets: Option<SnippetOptions>,
+    /// Every unqualified term must match instead of any of them.
+    pub conjunction: bool,
+    /// Drops the words the query syntax can't parse, like a leading wildcard or an unclosed
+    /// phrase, instead of failing, `ignored_syntax` tells which ones.
+    pub lenient: bool,
+    /// Best hits skipped, after dropping the duplicated ids, the handle default one if none.
+    /// Ignored by grouped searches.
+    pub offset: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SnippetOptions {
+    /// Pairs of the field whose query terms are highlighted and the stored field holding its
+    /// text, the same one unless the text is indexed again in an unstored field, like
+    /// `question_raw`. Snippets are keyed by the stored field name.
+    pub fields: Vec<(Field, Field)>,
+    /// Length in characters of the highlighted fragment of each field.
+    pub max_chars: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sort {
+    pub field: Field,
+    pub order: SortOrder,
 }
 
+/// Everything deciding the order of the hits of a search.
+#[derive(Clone, Copy)]
+struct Ranking {
+    rank_field: Option<Field>,
+    boost: ScoreBoost,
+    min_score: Option<Score>,
+    bm25: Option<Bm25>,
+    sort: Option<Sort>,
+    tie_breaker: Option<Field>,
+    segment_top_docs: Option<usize>,
+}
+
+/// Size of the index the reader searches, none of it retrieved.
+pub struct IndexStats {
+    /// Documents searched, the deleted ones aside.
+    pub num_docs: u64,
+    /// Deleted documents still taking space in their segments until they're merged.
+    pub deleted_docs: u64,
+    pub segments: usize,
+    /// Opstamp of the last commit of the index the reader sees, at least.
+    pub opstamp: u64,
+}
+
+/// Snapshot of the index the reader searches.
+pub struct ReaderGeneration {
+    /// Incremented on every reload, identifies the searcher.
+    pub generation: u64,
+    /// Opstamp of the last commit of the index the reader sees, at least.
+    pub opstamp: u64,
+}
+
+#[derive(Clone)]
 pub struct SearchDocument {
     pub doc: Document,
     pub score: Score,
+    /// Highlighted html fragment per stored field name, only of the fields matching the query.
+    pub snippets: BTreeMap<String, String>,
+    /// Segment and doc id the hit was retrieved from, `None` for exported documents.
+    pub doc_address: Option<DocAddress>,
 }
 
 impl IndexActorHandle {
-    pub async fn new(dir: impl Directory, schema: Schema, index_name: String, backend_env: AppEnv) -> Result<Self, TantivyError> {
+    /// Unqualified query terms only search the definition default fields, any other indexed
+    /// field can still be searched with a field-qualified query like `id:...`.
+    pub async fn new(dir: impl Into<Box<dyn Directory>>, definition: IndexDefinition, config: IndexerConfig) -> Result<Self, SearchError> {
+        let IndexDefinition { name, schema, schema_version, default_fields, default_sort, tie_breaker, derive_fields, expiry_field, .. } = definition;
+        let index_name = String::from(name);
         let schema_clone = schema.clone();
-        let (sender, receiver) = mpsc::channel(8);
-        let actor = IndexActor::new(index_name.clone(), dir, schema, receiver)?;
+        let (sender, receiver) = mpsc::channel(config.channel_capacity);
+
+        // Erasing the index while the Go backend is down would leave it empty until the backend
+        // is back, so it's kept and the boot fails instead
+        let dir: Box<dyn Directory> = dir.into();
+        if let Some(health_url) = &config.backend_health_url {
+            if !config.read_only && must_erase(dir.as_ref(), &schema, schema_version, config.mode) {
+                check_backend_health(&index_name, health_url).await?;
+            }
+        }
+        let mut actor = IndexActor::new(index_name.clone(), dir, schema, schema_version, receiver, &config, derive_fields)?;
 
-        if actor.must_reindex {
+        let reindexing = actor.must_reindex.clone();
+        if reindexing.load(Ordering::Relaxed) {
             let _ = sender
-                .send(IndexActorMessage::Reindex { backend_env })
+                .send(IndexActorMessage::Reindex { respond_to: None })
                 .await;
         }
 
@@ -40,21 +206,89 @@ impl IndexActorHandle {
             .reload_policy(ReloadPolicy::OnCommit)
             .try_into()?;
 
-        let fields = schema_clone
-            .fields()
-            .filter(|f| f.1.is_indexed()) // only search by indexed fields
-            .map(|f| f.0)
-            .collect();
-        let query_parser = QueryParser::new(schema_clone, fields, actor.index.tokenizers().clone());
+        if config.warmup_on_start {
+            warmup(&reader, &index_name)?;
+        }
+
+        let mut fields = Vec::with_capacity(default_fields.len());
+        for field_name in default_fields {
+            match schema_clone.get_field(field_name) {
+                Some(f) if schema_clone.get_field_entry(f).is_indexed() => fields.push(f),
+                _ => return Err(SearchError::Schema(format!("{} default search field must be an indexed field of the {} schema", field_name, index_name))),
+            }
+        }
+        let tie_breaker = match tie_breaker {
+            Some(field_name) => match schema_clone.get_field(field_name) {
+                Some(f) if schema_clone.get_field_entry(f).is_stored() => Some(f),
+                _ => return Err(SearchError::Schema(format!("{} tie breaker field must be a stored field of the {} schema", field_name, index_name))),
+            },
+            None => None,
+        };
+        let expiry_field = match expiry_field {
+            Some(field_name) => match schema_clone.get_field(field_name) {
+                Some(f) if schema_clone.get_field_entry(f).is_indexed() => Some(f),
+                _ => return Err(SearchError::Schema(format!("{} expiry field must be an indexed field of the {} schema", field_name, index_name))),
+            },
+            None => None,
+        };
+        let tokenizers = search_tokenizer_manager(config.max_token_len);
+        let field_boosts = schema_field_boosts(&schema_clone, &index_name, config.field_boosts.get(name));
+        let query_parser = new_query_parser(&schema_clone, fields.clone(), &tokenizers, &field_boosts);
+
+        if !config.read_only {
+            tokio::spawn(run_commit_index(actor.commit_schedule(), index_name.clone(), config.commit_interval));
+            if let Some(field) = expiry_field {
+                tokio::spawn(run_expire_index(sender.clone(), index_name.clone(), field, config.expiry_sweep_interval));
+            }
+        }
+        let runtime = tokio::runtime::Handle::current();
+        thread::spawn(move || run_index_actor(actor, runtime));
+
+        let query_cache = config.query_cache_size.map(|capacity| Arc::new(QueryCache::new(capacity, config.query_cache_ttl)));
+
+        Ok(Self { index_name, sender, reader, query_parser, default_fields: fields, tokenizers, schema: schema_clone, search_timeout: config.search_timeout, bm25: config.bm25, default_sort, tie_breaker, segment_top_docs: config.segment_top_docs, recency_half_life: config.recency_half_life, default_limit: config.default_limit, default_offset: config.default_offset, query_cache, max_query_terms: config.max_query_terms, read_only: config.read_only, field_boosts, reindexing })
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    pub fn name(&self) -> &str {
+        &self.index_name
+    }
+
+    /// Messages the actor queue can still take before senders have to wait for it.
+    pub fn queue_free(&self) -> usize {
+        self.sender.capacity()
+    }
 
-        tokio::spawn(run_commit_index(sender.clone(), index_name));
-        thread::spawn(move || run_index_actor(actor));
+    /// The requested limit, or the default one, between 1 and `MAX_SEARCH_LIMIT`.
+    pub fn limit(&self, requested: Option<usize>) -> usize {
+        requested.unwrap_or(self.default_limit).clamp(1, MAX_SEARCH_LIMIT)
+    }
 
-        Ok(Self { sender, reader, query_parser })
+    /// The requested offset, or the default one, rejected beyond `MAX_SEARCH_OFFSET`.
+    fn offset(&self, requested: Option<usize>) -> Result<usize, SearchError> {
+        match requested.unwrap_or(self.default_offset) {
+            offset if offset > MAX_SEARCH_OFFSET => Err(SearchError::Query(format!("offset must be at most {}, got: {}", MAX_SEARCH_OFFSET, offset))),
+            offset => Ok(offset),
+        }
     }
 
-    pub async fn index_single(&self, doc: Document) {
-        let _ = self.sender.send(IndexActorMessage::Single { doc }).await;
+    /// Whether the actor thread is still receiving messages.
+    pub fn is_alive(&self) -> bool {
+        !self.sender.is_closed()
+    }
+
+    /// Whether the index was erased for a schema change and the Go backend didn't answer the
+    /// reindex request yet, searches would miss most documents meanwhile.
+    pub fn is_reindexing(&self) -> bool {
+        self.reindexing.load(Ordering::Relaxed)
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn index_single(&self, doc: Document) -> Result<(), SearchError> {
+        self.send(IndexActorMessage::Single { doc }).await
     }
 
     #[cfg(test)]
@@ -65,28 +299,863 @@ impl IndexActorHandle {
             .unwrap_or_else(|_| panic!("{} index actor has been killed for commit while testing", index_name.clone()));
     }
 
-    pub async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchDocument>, TantivyError> {
+    #[cfg(test)]
+    pub fn with_query_cache(mut self, query_cache: QueryCache) -> Self {
+        self.query_cache = Some(Arc::new(query_cache));
+        self
+    }
+
+    /// Returns at most one document per id, the highest scoring one. Served from the query
+    /// cache, when enabled, until the reader reloads.
+    #[tracing::instrument(skip(self, options))]
+    pub async fn search(&self, query: &str, limit: usize, options: SearchOptions) -> Result<Vec<SearchDocument>, SearchError> {
+        self.timed_search(query, limit, options).await.map(|(docs, _)| docs)
+    }
+
+    /// Like `search`, along with how long it took to find and retrieve the hits, a cached
+    /// search taking next to nothing.
+    pub async fn timed_search(&self, query: &str, limit: usize, options: SearchOptions) -> Result<(Vec<SearchDocument>, Duration), SearchError> {
+        let started = Instant::now();
+        // Never waits, not even while the reader reloads on a commit: tantivy swaps the searcher
+        // atomically and this only clones the current one, so there is nothing to retry or time
+        // out here. Slow searches are bounded by `SEARCH_TIMEOUT_MS` below instead.
         let searcher = self.reader.searcher();
-        let query = self.query_parser.parse_query(query)?;
+        let generation = searcher.generation().generation_id();
+        let cache_key = self.query_cache.as_ref().map(|_| format!("{:?}", (query, limit, &options)));
+        if let (Some(cache), Some(key)) = (&self.query_cache, &cache_key) {
+            if let Some(docs) = cache.get(key, generation) {
+                return Ok((docs, started.elapsed()));
+            }
+        }
+
+        let ranking = self.ranking(query, &options);
+        let query = self.filtered_query(query, &options)?;
+        let offset = self.offset(options.offset)?;
+        let stored_fields = options.stored_fields;
+        let snippet_options = options.snippets;
 
         let search_task = tokio::task::spawn_blocking(move || {
-            let top_docs = searcher.search(&query, &TopDocs::with_limit(limit))?;
-            let mut docs = Vec::with_capacity(limit);
+            let generators = snippet_generators(&searcher, query.as_ref(), &snippet_options)?;
+            // Sized by the hits found, a large limit may match a few documents only
+            let top_docs = top_docs(&searcher, &query, limit.saturating_add(offset), ranking)?;
+            let mut unique_ids = UniqueIds::new(&searcher, top_docs.len());
+            let mut docs = Vec::with_capacity(top_docs.len());
+            let mut skipped = 0;
             for (score, doc_address) in top_docs {
                 let retrieved_doc = searcher.doc(doc_address)?;
-                docs.push(SearchDocument { doc: retrieved_doc, score });
+                if !unique_ids.insert(&retrieved_doc) {
+                    continue;
+                }
+                if skipped < offset {
+                    skipped += 1;
+                    continue;
+                }
+                let snippets = snippets(&searcher, &generators, &retrieved_doc);
+                docs.push(SearchDocument { doc: retain_fields(retrieved_doc, &stored_fields), score, snippets, doc_address: Some(doc_address) });
             }
 
             Ok(docs)
         });
 
-        search_task.await.unwrap()
+        // On timeout the blocking task is detached, it keeps its thread until it finishes but
+        // the request answers right away.
+        let result = match self.search_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, search_task).await {
+                Ok(result) => result.unwrap(),
+                Err(_) => Err(SearchError::timeout(format!("{} search timed out after {:?}", self.index_name, timeout))),
+            },
+            None => search_task.await.unwrap(),
+        };
+
+        if let (Some(cache), Some(key), Ok(docs)) = (&self.query_cache, cache_key, &result) {
+            cache.insert(key, generation, docs.clone());
+        }
+
+        result.map(|docs| (docs, started.elapsed()))
+    }
+
+    /// The hits bucketed by the stored text of `group_field`, at most `group_size` each, the
+    /// buckets ordered by their best hit. Tantivy doesn't group, so the top `candidates` hits
+    /// are retrieved and grouped here: a bucket whose hits all rank below them is missing or
+    /// short, even though it has enough matches.
+    pub async fn search_grouped(&self, query: &str, candidates: usize, group_field: Field, group_size: usize, mut options: SearchOptions) -> Result<Vec<(String, Vec<SearchDocument>)>, SearchError> {
+        if let Some(stored_fields) = &mut options.stored_fields {
+            stored_fields.push(group_field);
+        }
+
+        let mut groups: Vec<(String, Vec<SearchDocument>)> = Vec::new();
+        options.offset = Some(0);
+        for sdoc in self.search(query, candidates, options).await? {
+            let value = field_to_string(&sdoc.doc, group_field);
+            match groups.iter_mut().find(|(v, _)| *v == value) {
+                Some((_, docs)) if docs.len() < group_size => docs.push(sdoc),
+                Some(_) => {}
+                None => groups.push((value, vec![sdoc])),
+            }
+        }
+
+        Ok(groups)
+    }
+
+    /// Like `search` but sends every document as soon as it's retrieved, so callers stream them
+    /// instead of holding all of them in memory. The first error ends the stream, and so does
+    /// the receiver being dropped. Not bounded by `SEARCH_TIMEOUT_MS`.
+    pub fn search_stream(&self, query: &str, limit: usize, options: SearchOptions) -> Result<mpsc::Receiver<Result<SearchDocument, SearchError>>, SearchError> {
+        let searcher = self.reader.searcher();
+        let ranking = self.ranking(query, &options);
+        let query = self.filtered_query(query, &options)?;
+        let offset = self.offset(options.offset)?;
+        let stored_fields = options.stored_fields;
+        let snippet_options = options.snippets;
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+
+        tokio::task::spawn_blocking(move || {
+            let top_docs = match top_docs(&searcher, &query, limit.saturating_add(offset), ranking) {
+                Ok(top_docs) => top_docs,
+                Err(e) => {
+                    let _ = sender.blocking_send(Err(e.into()));
+                    return;
+                }
+            };
+
+            let generators = match snippet_generators(&searcher, query.as_ref(), &snippet_options) {
+                Ok(generators) => generators,
+                Err(e) => {
+                    let _ = sender.blocking_send(Err(e.into()));
+                    return;
+                }
+            };
+
+            let mut unique_ids = UniqueIds::new(&searcher, top_docs.len());
+            let mut skipped = 0;
+            for (score, doc_address) in top_docs {
+                let result = match searcher.doc(doc_address) {
+                    Ok(doc) if !unique_ids.insert(&doc) => continue,
+                    Ok(_) if skipped < offset => {
+                        skipped += 1;
+                        continue;
+                    }
+                    Ok(doc) => {
+                        let snippets = snippets(&searcher, &generators, &doc);
+                        Ok(SearchDocument { doc: retain_fields(doc, &stored_fields), score, snippets, doc_address: Some(doc_address) })
+                    }
+                    Err(e) => Err(e.into()),
+                };
+                let is_err = result.is_err();
+                if sender.blocking_send(result).is_err() || is_err {
+                    break;
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+
+    /// Sends every stored document of the index, segment by segment, without scoring nor sorting
+    /// them, for dumping the whole index. Like `search_stream`, the first error or dropping the
+    /// receiver ends it.
+    pub fn export(&self) -> mpsc::Receiver<Result<SearchDocument, SearchError>> {
+        let searcher = self.reader.searcher();
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+
+        tokio::task::spawn_blocking(move || {
+            for segment_reader in searcher.segment_readers() {
+                let store_reader = match segment_reader.get_store_reader(1) {
+                    Ok(store_reader) => store_reader,
+                    Err(e) => {
+                        let _ = sender.blocking_send(Err(e.into()));
+                        return;
+                    }
+                };
+
+                for doc in store_reader.iter(segment_reader.alive_bitset()) {
+                    let result = doc.map(|doc| SearchDocument { doc, score: 1.0, snippets: BTreeMap::new(), doc_address: None }).map_err(SearchError::from);
+                    let is_err = result.is_err();
+                    if sender.blocking_send(result).is_err() || is_err {
+                        return;
+                    }
+                }
+            }
+        });
+
+        receiver
+    }
+
+    /// Parses the query without running it, returning its debug form.
+    pub fn validate(&self, query: &str) -> Result<String, SearchError> {
+        Ok(format!("{:?}", self.parse_query(query, &SearchOptions::default(), &mut Vec::new())?))
+    }
+
+    /// Counts the documents matching the query without retrieving any of them.
+    pub async fn count(&self, query: &str, options: SearchOptions) -> Result<usize, SearchError> {
+        let searcher = self.reader.searcher();
+        let query = self.filtered_query(query, &options)?;
+
+        let count_task = tokio::task::spawn_blocking(move || searcher.search(&query, &Count));
+
+        Ok(count_task.await.unwrap()?)
+    }
+
+    /// Tokens and positions the named analyzer turns the text into when indexing it, or when
+    /// parsing it as a query with `search`. `None` when no analyzer has that name.
+    pub fn analyze(&self, tokenizer_name: &str, text: &str, search: bool) -> Option<Vec<(String, usize)>> {
+        let analyzer = match search {
+            true => self.tokenizers.get(tokenizer_name),
+            false => self.reader.searcher().index().tokenizers().get(tokenizer_name),
+        }?;
+
+        let mut tokens = Vec::new();
+        analyzer.token_stream(text).process(&mut |token| tokens.push((token.text.clone(), token.position)));
+        Some(tokens)
+    }
+
+    /// Whether a document has the given id, counted on its term without retrieving it.
+    pub async fn exists(&self, id: &str) -> Result<bool, SearchError> {
+        let searcher = self.reader.searcher();
+        let query = TermQuery::new(id_term(searcher.schema(), id)?, IndexRecordOption::Basic);
+
+        let exists_task = tokio::task::spawn_blocking(move || searcher.search(&query, &Count));
+
+        Ok(exists_task.await.unwrap()? > 0)
+    }
+
+    /// Explains how the query scores the document with the given id, `None` when there is no
+    /// document with that id.
+    pub async fn explain(&self, query: &str, id: &str) -> Result<Option<Explanation>, SearchError> {
+        let query = self.parse_query(query, &SearchOptions::default(), &mut Vec::new())?;
+        let searcher = self.reader.searcher();
+        let id_term = id_term(searcher.schema(), id)?;
+
+        let explain_task = tokio::task::spawn_blocking(move || {
+            match find_doc_address(&searcher, id_term)? {
+                Some(doc_address) => Ok(Some(query.explain(&searcher, doc_address)?)),
+                None => Ok(None),
+            }
+        });
+
+        explain_task.await.unwrap()
+    }
+
+    /// The stored documents of the given ids in their order, `None` for the ids without one.
+    /// Each id is looked up on its own, the documents are not scored.
+    pub async fn mget(&self, ids: Vec<String>) -> Result<Vec<Option<SearchDocument>>, SearchError> {
+        let searcher = self.reader.searcher();
+        let id_terms = ids.iter()
+            .map(|id| id_term(searcher.schema(), id))
+            .collect::<Result<Vec<Term>, SearchError>>()?;
+
+        let mget_task = tokio::task::spawn_blocking(move || {
+            id_terms.into_iter()
+                .map(|id_term| match find_doc_address(&searcher, id_term)? {
+                    Some(doc_address) => {
+                        let doc = searcher.doc(doc_address)?;
+                        Ok(Some(SearchDocument { doc, score: 0.0, snippets: BTreeMap::new(), doc_address: Some(doc_address) }))
+                    }
+                    None => Ok(None),
+                })
+                .collect()
+        });
+
+        mget_task.await.unwrap()
+    }
+
+    /// Up to `limit` distinct texts of `stored_field` completing the prefix, which is analyzed
+    /// like `search_field`: its last word is expanded to the terms it starts, and also to the
+    /// ones starting within a typo of it once it has 3 characters, the exact expansions ranking
+    /// first. The previous words must match as typed.
+    pub async fn autocomplete(&self, prefix: &str, search_field: Field, stored_field: Field, limit: usize) -> Result<Vec<String>, SearchError> {
+        let tokenizer = self.field_tokenizer(search_field)
+            .ok_or_else(|| SearchError::Schema(format!("{} autocomplete field must be an indexed text field", self.index_name)))?;
+        let mut words = Vec::new();
+        tokenizer.token_stream(prefix).process(&mut |token| words.push(token.text.clone()));
+        let last_word = match words.pop() {
+            Some(word) if limit > 0 => word,
+            _ => return Ok(Vec::new()),
+        };
+
+        let last_term = Term::from_field_text(search_field, &last_word);
+        let mut expansions: Vec<(Occur, Box<dyn Query>)> = vec![
+            (Occur::Should, Box::new(BoostQuery::new(Box::new(FuzzyTermQuery::new_prefix(last_term.clone(), 0, true)), 2.0))),
+        ];
+        if last_word.chars().count() >= MIN_FUZZY_PREFIX_LEN {
+            expansions.push((Occur::Should, Box::new(FuzzyTermQuery::new_prefix(last_term, 1, true))));
+        }
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = words.iter()
+            .map(|word| (Occur::Must, Box::new(TermQuery::new(Term::from_field_text(search_field, word), IndexRecordOption::Basic)) as Box<dyn Query>))
+            .collect();
+        clauses.push((Occur::Must, Box::new(BooleanQuery::new(expansions))));
+        let query = BooleanQuery::new(clauses);
+
+        let searcher = self.reader.searcher();
+        let autocomplete_task = tokio::task::spawn_blocking(move || {
+            let mut suggestions: Vec<String> = Vec::with_capacity(limit);
+            for (_, doc_address) in searcher.search(&query, &TopDocs::with_limit(limit * SUGGESTION_CANDIDATES))? {
+                let suggestion = field_to_string(&searcher.doc(doc_address)?, stored_field);
+                if !suggestion.is_empty() && !suggestions.contains(&suggestion) {
+                    suggestions.push(suggestion);
+                }
+                if suggestions.len() == limit {
+                    break;
+                }
+            }
+
+            Ok(suggestions)
+        });
+
+        autocomplete_task.await.unwrap()
+    }
+
+    /// Browsing with an empty query has no relevance to rank by, so the index default sort
+    /// applies unless another one was requested.
+    fn ranking(&self, query: &str, options: &SearchOptions) -> Ranking {
+        let sort = match options.sort {
+            Some(sort) => Some(sort),
+            None if query.trim().is_empty() => self.default_sort,
+            None => None,
+        };
+
+        let recency = options.recency_field.map(|field| {
+            let now_millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+            Recency { field, half_life: self.recency_half_life, now_millis }
+        });
+        let boost = ScoreBoost { field: options.boost_field, recency };
+
+        Ranking { rank_field: options.rank_field, boost, min_score: options.min_score, bm25: self.bm25, sort, tie_breaker: self.tie_breaker, segment_top_docs: self.segment_top_docs }
+    }
+
+    /// Parses the query and makes every filter term mandatory on top of it.
+    fn filtered_query(&self, query: &str, options: &SearchOptions) -> Result<Box<dyn Query>, SearchError> {
+        let query = self.parse_query(query, options, &mut Vec::new())?;
+        if options.filters.is_empty() {
+            return Ok(query);
+        }
+
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Must, query)];
+        for term in &options.filters {
+            clauses.push((Occur::Must, Box::new(TermQuery::new(term.clone(), IndexRecordOption::Basic))));
+        }
+
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    /// Why each word a lenient search drops was ignored, empty for a strict one or when the
+    /// query fails anyway.
+    pub fn ignored_syntax(&self, query: &str, options: &SearchOptions) -> Vec<String> {
+        let mut ignored = Vec::new();
+        if options.lenient {
+            let _ = self.parse_query(query, options, &mut ignored);
+        }
+
+        ignored
+    }
+
+    /// An empty query browses the whole index instead of failing to parse. Words ending in `*`
+    /// match every term starting with them, in the default fields or in the given one, like
+    /// `caba*` or `id:abc*`. Leading wildcards would walk the whole term dictionary, so they're
+    /// rejected.
+    ///
+    /// A whole `id:<value>` query is an exact id lookup that skips the query parser, so ids with
+    /// characters of the query syntax, like `:` or `/`, or another casing still match.
+    ///
+    /// Any other term is parsed with tantivy's syntax, which boosts a term, a phrase or a group
+    /// out of the box, like `question:caballo^3` or `(caballo blanco)^1.5`. Custom BM25 parameters
+    /// rescore the hits ignoring those boosts.
+    ///
+    /// Unqualified terms search the default fields of the options, the index ones when `None`.
+    /// Lenient options push why every dropped word was ignored to `ignored` instead of failing.
+    fn parse_query(&self, query: &str, options: &SearchOptions, ignored: &mut Vec<String>) -> Result<Box<dyn Query>, SearchError> {
+        let query = query.trim();
+        if query.is_empty() {
+            return Ok(Box::new(AllQuery));
+        }
+
+        if let Some(id) = query.strip_prefix("id:") {
+            if !id.is_empty() && !id.ends_with('*') && !id.contains(char::is_whitespace) {
+                return Ok(Box::new(TermQuery::new(id_term(&self.schema, id)?, IndexRecordOption::Basic)));
+            }
+        }
+
+        let default_fields = options.default_fields.as_deref();
+        if let Some(max_query_terms) = self.max_query_terms {
+            let terms = self.count_query_terms(query, default_fields);
+            if terms > max_query_terms {
+                return Err(SearchError::Query(format!("query analyzed into {} terms, at most {} are allowed, try a shorter query", terms, max_query_terms)));
+            }
+        }
+
+        let occur = if options.conjunction { Occur::Must } else { Occur::Should };
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        let mut words = Vec::new();
+        let mut dropped_words = false;
+        for word in query.split_whitespace() {
+            let value = word.split_once(':').map(|(_, value)| value).unwrap_or(word);
+            if value.starts_with('*') {
+                let error = format!("leading wildcards are not supported: {}", word);
+                if !options.lenient {
+                    return Err(SearchError::Query(error));
+                }
+                ignored.push(error);
+                dropped_words = true;
+                continue;
+            }
+
+            match word.strip_suffix('*') {
+                Some(prefix) => {
+                    if let Some(query) = self.prefix_query(prefix, default_fields)? {
+                        clauses.push((occur, query));
+                    }
+                }
+                None => words.push(word),
+            }
+        }
+
+        if clauses.is_empty() && !dropped_words {
+            return self.parse_words(query, options, ignored);
+        }
+        if !words.is_empty() {
+            clauses.push((occur, self.parse_words(words.join(" ").as_str(), options, ignored)?));
+        }
+
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    /// Lenient options parse every word on its own when the whole text fails, dropping the ones
+    /// still failing.
+    fn parse_words(&self, words: &str, options: &SearchOptions, ignored: &mut Vec<String>) -> Result<Box<dyn Query>, SearchError> {
+        let query_parser = match (&options.default_fields, options.conjunction) {
+            (None, false) => self.query_parser.clone(),
+            (default_fields, conjunction) => {
+                let fields = default_fields.clone().unwrap_or_else(|| self.default_fields.clone());
+                let mut query_parser = new_query_parser(&self.schema, fields, &self.tokenizers, &self.field_boosts);
+                if conjunction {
+                    query_parser.set_conjunction_by_default();
+                }
+                query_parser
+            }
+        };
+
+        let error = match query_parser.parse_query(words) {
+            Ok(query) => return Ok(query),
+            Err(e) if !options.lenient => return Err(e.into()),
+            Err(e) => e,
+        };
+        tracing::debug!("lenient parse of {} after {:?}", words, error);
+
+        let occur = if options.conjunction { Occur::Must } else { Occur::Should };
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        for word in words.split_whitespace() {
+            match query_parser.parse_query(word) {
+                Ok(query) => clauses.push((occur, query)),
+                Err(e) => ignored.push(format!("{}: {}", word, SearchError::from(e))),
+            }
+        }
+
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    /// Matches the prefix in any of the fields searched, `None` when every analyzer drops it.
+    fn prefix_query(&self, prefix: &str, default_fields: Option<&[Field]>) -> Result<Option<Box<dyn Query>>, SearchError> {
+        let (fields, prefix) = match prefix.split_once(':') {
+            Some((field_name, prefix)) => match self.schema.get_field(field_name) {
+                Some(field) => (vec![field], prefix),
+                None => return Err(SearchError::Query(format!("unknown field {} in prefix query", field_name))),
+            },
+            None => (default_fields.unwrap_or(&self.default_fields).to_vec(), prefix),
+        };
+        if prefix.contains('*') {
+            return Err(SearchError::Query(format!("only trailing wildcards are supported: {}*", prefix)));
+        }
+
+        let mut queries: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        for field in fields {
+            // Analyzed like the indexed terms, so `Caba*` still matches the lowercased `caballo`
+            if let Some(term) = self.analyze_prefix(field, prefix)? {
+                let pattern = format!("{}.*", escape_regex(term.as_str()));
+                queries.push((Occur::Should, Box::new(RegexQuery::from_pattern(pattern.as_str(), field)?)));
+            }
+        }
+
+        Ok(match queries.is_empty() {
+            true => None,
+            false => Some(Box::new(BooleanQuery::new(queries))),
+        })
+    }
+
+    /// First token of the prefix, `None` when the analyzer drops it, like a stop word.
+    fn analyze_prefix(&self, field: Field, prefix: &str) -> Result<Option<String>, SearchError> {
+        let tokenizer = self.field_tokenizer(field)
+            .ok_or_else(|| SearchError::Query(format!("prefix queries need an indexed text field, {} is not", self.schema.get_field_entry(field).name())))?;
+
+        let mut token_stream = tokenizer.token_stream(prefix);
+        Ok(if token_stream.advance() { Some(token_stream.token().text.clone()) } else { None })
+    }
+
+    /// Tokens the whole query text is analyzed into by the default field yielding the most of
+    /// them, syntax like field names or boosts included, so it's an upper bound of the terms
+    /// the parsed query searches per field.
+    fn count_query_terms(&self, query: &str, default_fields: Option<&[Field]>) -> usize {
+        default_fields.unwrap_or(&self.default_fields)
+            .iter()
+            .filter_map(|field| self.field_tokenizer(*field))
+            .map(|tokenizer| {
+                let mut terms = 0;
+                tokenizer.token_stream(query).process(&mut |_| terms += 1);
+                terms
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Analyzer of an indexed text field, as registered in the search tokenizers.
+    fn field_tokenizer(&self, field: Field) -> Option<TextAnalyzer> {
+        match self.schema.get_field_entry(field).field_type() {
+            FieldType::Str(options) => options.get_indexing_options().and_then(|indexing| self.tokenizers.get(indexing.tokenizer())),
+            _ => None,
+        }
+    }
+
+    /// Counts the documents matching the query per first level value of the given facet field,
+    /// the whole index with an empty query. `min_score` is ignored, matches aren't scored.
+    pub async fn facet_counts(&self, query: &str, options: &SearchOptions, field: Field) -> Result<BTreeMap<String, u64>, SearchError> {
+        let searcher = self.reader.searcher();
+        let query = self.filtered_query(query, options)?;
+
+        let facet_task = tokio::task::spawn_blocking(move || {
+            let mut collector = FacetCollector::for_field(field);
+            collector.add_facet("/");
+            let facet_counts = searcher.search(&query, &collector)?;
+
+            Ok(facet_counts
+                .get("/")
+                .map(|(facet, count)| (facet.to_path().join("/"), count))
+                .collect())
+        });
+
+        facet_task.await.unwrap()
+    }
+
+    /// Counts the documents per distinct value of an untokenized text field, walking the term
+    /// dictionary of every segment. Values only held by deleted documents are left out.
+    pub async fn term_counts(&self, field: Field) -> Result<BTreeMap<String, u64>, SearchError> {
+        let searcher = self.reader.searcher();
+
+        let terms_task = tokio::task::spawn_blocking(move || {
+            let mut values = BTreeSet::new();
+            for segment_reader in searcher.segment_readers() {
+                let inverted_index = segment_reader.inverted_index(field)?;
+                let mut terms = inverted_index.terms().stream()?;
+                while terms.advance() {
+                    values.insert(String::from_utf8_lossy(terms.key()).to_string());
+                }
+            }
+
+            let mut counts = BTreeMap::new();
+            for value in values {
+                let query = TermQuery::new(Term::from_field_text(field, value.as_str()), IndexRecordOption::Basic);
+                let count = searcher.search(&query, &Count)? as u64;
+                if count > 0 {
+                    counts.insert(value, count);
+                }
+            }
+
+            Ok(counts)
+        });
+
+        terms_task.await.unwrap()
+    }
+
+    /// Forces a full reindex from the Go backend, no matter if the schema changed or not.
+    /// Returns the HTTP status answered by the backend.
+    pub async fn reindex(&self) -> Result<StatusCode, SearchError> {
+        let (respond_to, response) = oneshot::channel();
+
+        self.send(IndexActorMessage::Reindex { respond_to: Some(respond_to) }).await?;
+
+        response
+            .await
+            .map_err(|_| self.actor_unavailable())?
+    }
+
+    pub fn stats(&self) -> Result<IndexStats, SearchError> {
+        let searcher = self.reader.searcher();
+        let opstamp = searcher.index().load_metas()?.opstamp;
+        let deleted_docs = searcher.segment_readers().iter().map(|s| u64::from(s.num_deleted_docs())).sum();
+
+        Ok(IndexStats { num_docs: searcher.num_docs(), deleted_docs, segments: searcher.segment_readers().len(), opstamp })
+    }
+
+    /// Makes the committed documents visible right away instead of waiting for the reader to
+    /// notice the commit.
+    pub fn reload(&self) -> Result<ReaderGeneration, SearchError> {
+        // Read before reloading, a commit landing in between is then visible but not reported
+        let opstamp = self.reader.searcher().index().load_metas()?.opstamp;
+        self.reader.reload()?;
+
+        Ok(ReaderGeneration { generation: self.reader.searcher().generation().generation_id(), opstamp })
+    }
+
+    /// Commits the pending changes, answering the opstamp of the last commit once they're
+    /// committed, so every change sent before is searchable after the reader reloads. A
+    /// read-only index has none.
+    pub async fn flush(&self) -> Result<u64, SearchError> {
+        if self.read_only {
+            return Ok(self.reader.searcher().index().load_metas()?.opstamp);
+        }
+        let (respond_to, response) = oneshot::channel();
+
+        self.send(IndexActorMessage::Flush { respond_to }).await?;
+
+        response
+            .await
+            .map_err(|_| self.actor_unavailable())?
     }
 
-    pub async fn delete(&self, id: String) {
+    /// Compacts the index into a single segment, returning the resulting segment count.
+    pub async fn merge(&self) -> Result<usize, SearchError> {
+        let (respond_to, response) = oneshot::channel();
+
+        self.send(IndexActorMessage::Merge { respond_to }).await?;
+
+        response
+            .await
+            .map_err(|_| self.actor_unavailable())?
+    }
+
+    /// Partially updates the document with the given id, `false` when there is no such document.
+    pub async fn patch(&self, id: String, fields: Vec<Field>, doc: Document) -> Result<bool, SearchError> {
+        let (respond_to, response) = oneshot::channel();
+
+        self.send(IndexActorMessage::Patch { id, fields, doc, respond_to }).await?;
+
+        response
+            .await
+            .map_err(|_| self.actor_unavailable())?
+    }
+
+    pub async fn delete(&self, id: String) -> Result<(), SearchError> {
+        self.send(IndexActorMessage::Delete { id }).await
+    }
+
+    /// Deletes every id in a single actor message, returning how many were accepted.
+    pub async fn delete_batch(&self, ids: Vec<String>) -> Result<usize, SearchError> {
+        let count = ids.len();
+        self.send(IndexActorMessage::DeleteBatch { ids }).await?;
+
+        Ok(count)
+    }
+
+    /// Fails instead of panicking when the actor thread is gone, so handlers can answer a 503.
+    /// Every message changes the index, so a read-only one refuses all of them.
+    async fn send(&self, msg: IndexActorMessage) -> Result<(), SearchError> {
+        if self.read_only {
+            return Err(SearchError::ReadOnly(self.index_name.clone()));
+        }
+
         self.sender
-            .send(IndexActorMessage::Delete { id: id.clone() })
+            .send(msg)
             .await
-            .unwrap_or_else(|_| panic!("{} index actor killed when deleting", id.clone()));
+            .map_err(|_| self.actor_unavailable())
+    }
+
+    fn actor_unavailable(&self) -> SearchError {
+        SearchError::ChannelClosed(self.index_name.clone())
+    }
+}
+
+/// Best hits first. With a boost, the text relevance is multiplied by the log2 of the boost
+/// field and decayed by the age of the hit. With custom BM25 parameters, tantivy's scores are
+/// replaced by `Bm25` ones. With a sort, hits are ordered by the sort field and then by
+/// relevance. With a rank field, by it alone.
+fn top_docs(searcher: &Searcher, query: &dyn Query, limit: usize, ranking: Ranking) -> Result<Vec<(Score, DocAddress)>, TantivyError> {
+    let segment_limit = segment_limit(ranking.segment_top_docs, limit);
+    let mut top_docs = match (ranking.rank_field, ranking.sort, ranking.bm25) {
+        (Some(rank_field), _, _) => {
+            searcher.search(query, &SegmentLimited::new(TopDocs::with_limit(segment_limit).order_by_u64_field(rank_field), limit))?
+                .into_iter()
+                .map(|(rank, doc_address)| (rank as Score, doc_address))
+                .collect()
+        }
+        (None, Some(sort), _) => sorted_top_docs(searcher, query, limit, sort, ranking.tie_breaker, ranking.segment_top_docs)?,
+        (None, None, Some(bm25)) => searcher.search(query, &SegmentLimited::new(bm25.top_docs(searcher, query, segment_limit, ranking.boost)?, limit))?,
+        (None, None, None) if ranking.boost.is_none() => searcher.search(query, &SegmentLimited::new(TopDocs::with_limit(segment_limit), limit))?,
+        (None, None, None) => {
+            let boost = ranking.boost;
+            let collector = TopDocs::with_limit(segment_limit).tweak_score(move |segment_reader: &SegmentReader| boost.segment_boost(segment_reader));
+            searcher.search(query, &SegmentLimited::new(collector, limit))?
+        }
+    };
+
+    if let Some(min_score) = ranking.min_score {
+        top_docs.retain(|(score, _)| *score >= min_score);
     }
+
+    Ok(top_docs)
+}
+
+/// Hits kept per segment by the collectors of a search, SEGMENT_TOP_DOCS when lower than the
+/// limit, see `SegmentLimited`.
+fn segment_limit(segment_top_docs: Option<usize>, limit: usize) -> usize {
+    segment_top_docs.map_or(limit, |hits| hits.min(limit))
+}
+
+/// Hits ordered by the sort field then relevance, the ties among both by the stored text of
+/// `tie_breaker`. The hits tied with the last one within the limit are retrieved too, up to
+/// `MAX_TIED_HITS`, to order them, as the collector alone would pick the ones first in the index.
+fn sorted_top_docs(searcher: &Searcher, query: &dyn Query, limit: usize, sort: Sort, tie_breaker: Option<Field>, segment_top_docs: Option<usize>) -> Result<Vec<(Score, DocAddress)>, TantivyError> {
+    let sorted = |limit: usize| {
+        let collector = TopDocs::with_limit(segment_limit(segment_top_docs, limit)).tweak_score(move |segment_reader: &SegmentReader| {
+            let sort_reader = segment_reader.fast_fields().u64(sort.field).unwrap();
+
+            move |doc: DocId, score: Score| {
+                let value = sort_reader.get_val(doc);
+                match sort.order {
+                    SortOrder::Asc => (u64::MAX - value, score),
+                    SortOrder::Desc => (value, score),
+                }
+            }
+        });
+        searcher.search(query, &SegmentLimited::new(collector, limit))
+    };
+
+    let tie_breaker = match tie_breaker {
+        Some(field) if limit > 0 => field,
+        _ => return Ok(sorted(limit)?.into_iter().map(|((_, score), doc_address)| (score, doc_address)).collect()),
+    };
+
+    let mut fetched = limit + 1;
+    let mut hits = sorted(fetched)?;
+    while hits.len() == fetched && hits[fetched - 1].0 == hits[limit - 1].0 && fetched < limit + MAX_TIED_HITS {
+        fetched = (fetched * 2).min(limit + MAX_TIED_HITS);
+        hits = sorted(fetched)?;
+    }
+
+    let mut hits = hits.into_iter()
+        .map(|(key, doc_address)| Ok((key, field_to_string(&searcher.doc(doc_address)?, tie_breaker), doc_address)))
+        .collect::<Result<Vec<_>, TantivyError>>()?;
+    hits.sort_by(|(a, a_id, _), (b, b_id, _)| b.0.cmp(&a.0).then(b.1.total_cmp(&a.1)).then(a_id.cmp(b_id)));
+
+    Ok(hits.into_iter()
+        .take(limit)
+        .map(|((_, score), _, doc_address)| (score, doc_address))
+        .collect())
+}
+
+async fn check_backend_health(index_name: &str, health_url: &str) -> Result<(), SearchError> {
+    let response = reqwest::Client::new()
+        .get(health_url)
+        .timeout(BACKEND_HEALTH_TIMEOUT)
+        .send()
+        .await;
+
+    match response {
+        Ok(r) if r.status().is_success() => Ok(()),
+        Ok(r) => Err(SearchError::Reindex(format!("{} index must be erased and reindexed but {} answered {}, keeping it", index_name, health_url, r.status()))),
+        Err(e) => Err(SearchError::Reindex(format!("{} index must be erased and reindexed but {} is unreachable, keeping it: {:?}", index_name, health_url, e))),
+    }
+}
+
+/// Boosts of the fields of the schema, the unknown ones are ignored with a warning.
+fn schema_field_boosts(schema: &Schema, index_name: &str, boosts: Option<&HashMap<String, Score>>) -> Vec<(Field, Score)> {
+    let mut field_boosts = Vec::new();
+    for (field_name, boost) in boosts.into_iter().flatten() {
+        match schema.get_field(field_name) {
+            Some(field) => field_boosts.push((field, *boost)),
+            None => tracing::warn!("ignoring the boost of {}, not a field of the {} schema", field_name, index_name),
+        }
+    }
+
+    field_boosts
+}
+
+fn new_query_parser(schema: &Schema, default_fields: Vec<Field>, tokenizers: &TokenizerManager, field_boosts: &[(Field, Score)]) -> QueryParser {
+    let mut query_parser = QueryParser::new(schema.clone(), default_fields, tokenizers.clone());
+    for (field, boost) in field_boosts {
+        query_parser.set_field_boost(*field, *boost);
+    }
+
+    query_parser
+}
+
+/// One generator per snippet field, tantivy highlights the terms of a single field each.
+fn snippet_generators(searcher: &Searcher, query: &dyn Query, options: &Option<SnippetOptions>) -> Result<Vec<(Field, SnippetGenerator)>, TantivyError> {
+    let options = match options {
+        Some(options) => options,
+        None => return Ok(Vec::new()),
+    };
+
+    options.fields
+        .iter()
+        .map(|(searched, stored)| {
+            let mut generator = SnippetGenerator::create(searcher, query, *searched)?;
+            generator.set_max_num_chars(options.max_chars);
+            Ok((*stored, generator))
+        })
+        .collect()
+}
+
+/// Snippets of the stored fields containing any highlighted term, taken before the unrequested
+/// fields are dropped from the document.
+fn snippets(searcher: &Searcher, generators: &[(Field, SnippetGenerator)], doc: &Document) -> BTreeMap<String, String> {
+    let mut snippets = BTreeMap::new();
+    for (stored, generator) in generators {
+        let snippet = generator.snippet(field_to_string(doc, *stored).as_str());
+        if !snippet.is_empty() {
+            snippets.insert(searcher.schema().get_field_name(*stored).to_string(), snippet.to_html());
+        }
+    }
+
+    snippets
+}
+
+fn retain_fields(doc: Document, stored_fields: &Option<Vec<Field>>) -> Document {
+    match stored_fields {
+        Some(fields) => doc.into_iter()
+            .filter(|fv| fields.contains(&fv.field()))
+            .collect::<Vec<FieldValue>>()
+            .into(),
+        None => doc,
+    }
+}
+
+/// Hits come best first, so keeping the first doc per id keeps the highest scoring one when a
+/// stale duplicate survived in another segment.
+struct UniqueIds {
+    id_field: Option<Field>,
+    seen: HashSet<String>,
+}
+
+impl UniqueIds {
+    fn new(searcher: &Searcher, capacity: usize) -> Self {
+        UniqueIds { id_field: searcher.schema().get_field("id"), seen: HashSet::with_capacity(capacity) }
+    }
+
+    /// Whether no document with the same id was inserted before.
+    fn insert(&mut self, doc: &Document) -> bool {
+        match self.id_field {
+            Some(id_field) => self.seen.insert(field_to_string(doc, id_field)),
+            None => true,
+        }
+    }
+}
+
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$#&-~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// The first search after boot is slow while segments aren't in the page cache yet, so a
+/// trivial match-all query loads them before serving real traffic.
+fn warmup(reader: &IndexReader, index_name: &str) -> Result<(), TantivyError> {
+    let started = Instant::now();
+    reader.searcher().search(&AllQuery, &TopDocs::with_limit(1))?;
+    tracing::info!("{} searcher warmed up in {:?}", index_name, started.elapsed());
+
+    Ok(())
 }
\ No newline at end of file
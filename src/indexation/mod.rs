@@ -1,17 +1,206 @@
+use serde_json::Value;
 use tantivy::Document;
-use tantivy::schema::{Field, IndexRecordOption, TextFieldIndexing, TextOptions};
+use tantivy::schema::{Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions};
+use tantivy::tokenizer::{AsciiFoldingFilter, Language, LowerCaser, RemoveLongFilter, SimpleTokenizer, Stemmer, StopWordFilter, TextAnalyzer, TokenizerManager};
+
+use crate::indexation::edge_ngram::EdgeNgramFilter;
+use crate::indexation::handle::Sort;
 
 mod actor;
+pub mod cache;
+mod collector;
+mod edge_ngram;
 pub mod handle;
+mod scoring;
 
-pub fn ngram2_options() -> TextOptions {
-    let text_field_indexing = TextFieldIndexing::default()
-        .set_tokenizer("ngram2")
-        .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+pub use actor::{IndexMode, MergeSettings, normalize_id};
+pub use scoring::Bm25;
+
+pub const DEFAULT_MAX_TOKEN_LEN: usize = 40;
+
+/// Everything needed to spawn the actor and handle of one index.
+pub struct IndexDefinition {
+    /// Used in logs and to ask the Go backend for a reindex.
+    pub name: &'static str,
+    /// Directory under `index/` holding the index files.
+    pub directory: &'static str,
+    pub schema: Schema,
+    /// Bumped on purpose with every schema change, and with every analyzer change tantivy can't
+    /// see in the schema, so the index is erased and reindexed on the next start.
+    pub schema_version: u32,
+    /// Fields searched by unqualified query terms.
+    pub default_fields: &'static [&'static str],
+    /// Order of the searches browsing the index with an empty query, by relevance if none.
+    pub default_sort: Option<Sort>,
+    /// Stored text field ordering, lexicographically, the hits tied on a sort and relevance, so
+    /// paging through them is stable. Tied hits keep the index order if none.
+    pub tie_breaker: Option<&'static str>,
+    /// Re-adds the indexed only fields derived from the stored ones to a patched document, which
+    /// is rebuilt from its stored fields and would lose them otherwise.
+    pub derive_fields: Option<fn(Document) -> Document>,
+    /// Indexed u64 field with the epoch millis a document expires at, swept periodically by
+    /// `run_expire_index`. Documents without it never expire.
+    pub expiry_field: Option<&'static str>,
+}
+
+/// Analyzers a text field can be indexed with, all of them available through `tokenizer_manager`
+/// and `search_tokenizer_manager`.
+#[derive(Clone, Copy)]
+pub enum Analyzer {
+    /// Spanish words, lowercased, without accents nor stop words and stemmed.
+    Ngram2,
+    /// Like `Ngram2` but keeping the accents, so `él` and `el` are different terms.
+    Ngram2Accented,
+    /// Like `Ngram2` but not stemmed, so `caballo` and `caballos` are different terms.
+    Ngram2Unstemmed,
+    /// Lowercased parts of an address split on `@` and `.`, neither stemmed nor filtered, indexed
+    /// with their prefixes too so `joh` finds `john.doe@example.com`.
+    Email,
+    /// Lowercased words keeping their accents and stop words, for exact rather than stemmed
+    /// matches.
+    Exact,
+    /// The whole value as a single exact term, like a keyword.
+    Raw,
+}
+
+impl Analyzer {
+    pub fn tokenizer_name(&self) -> &'static str {
+        match self {
+            Analyzer::Ngram2 => "ngram2",
+            Analyzer::Ngram2Accented => "ngram2_accented",
+            Analyzer::Ngram2Unstemmed => "ngram2_unstemmed",
+            Analyzer::Email => "email_prefix",
+            Analyzer::Exact => "exact",
+            Analyzer::Raw => "raw",
+        }
+    }
+
+    /// `None` for the analyzers shipped with tantivy.
+    fn text_analyzer(&self, max_token_len: usize) -> Option<TextAnalyzer> {
+        match self {
+            Analyzer::Ngram2 => Some(es_ngram2_analyzer(max_token_len)),
+            Analyzer::Ngram2Accented => Some(es_accented_analyzer(max_token_len)),
+            Analyzer::Ngram2Unstemmed => Some(es_unstemmed_analyzer(max_token_len)),
+            Analyzer::Email => Some(email_analyzer(max_token_len).filter(EdgeNgramFilter::new(1))),
+            Analyzer::Exact => Some(exact_analyzer(max_token_len)),
+            Analyzer::Raw => None,
+        }
+    }
+
+    /// Like `text_analyzer` but for the query terms, which must not be expanded to their prefixes.
+    fn search_analyzer(&self, max_token_len: usize) -> Option<TextAnalyzer> {
+        match self {
+            Analyzer::Email => Some(email_analyzer(max_token_len)),
+            _ => self.text_analyzer(max_token_len),
+        }
+    }
+}
+
+const ANALYZERS: [Analyzer; 6] = [Analyzer::Ngram2, Analyzer::Ngram2Accented, Analyzer::Ngram2Unstemmed, Analyzer::Email, Analyzer::Exact, Analyzer::Raw];
+
+/// Tokenizers of every analyzer used by the index actor when indexing.
+pub fn tokenizer_manager(max_token_len: usize) -> TokenizerManager {
+    let tokenizers = TokenizerManager::default();
+    for analyzer in ANALYZERS {
+        if let Some(text_analyzer) = analyzer.text_analyzer(max_token_len) {
+            tokenizers.register(analyzer.tokenizer_name(), text_analyzer);
+        }
+    }
+
+    tokenizers
+}
+
+/// Tokenizers of every analyzer used by the handle when parsing queries, under the same names as
+/// `tokenizer_manager` so both always agree on which analyzer a field uses.
+pub fn search_tokenizer_manager(max_token_len: usize) -> TokenizerManager {
+    let tokenizers = TokenizerManager::default();
+    for analyzer in ANALYZERS {
+        if let Some(text_analyzer) = analyzer.search_analyzer(max_token_len) {
+            tokenizers.register(analyzer.tokenizer_name(), text_analyzer);
+        }
+    }
 
-    TextOptions::default()
-        .set_indexing_options(text_field_indexing)
-        .set_stored()
+    tokenizers
+}
+
+/// Tokens longer than `max_token_len`, MAX_TOKEN_LEN, are dropped. Changing it only affects the
+/// documents indexed afterwards, the others need a reindex.
+fn es_ngram2_analyzer(max_token_len: usize) -> TextAnalyzer {
+    TextAnalyzer::from(SimpleTokenizer)
+        .filter(RemoveLongFilter::limit(max_token_len))
+        .filter(LowerCaser)
+        .filter(AsciiFoldingFilter) // remove accents
+        .filter(StopWordFilter::new(Language::Spanish).unwrap())
+        .filter(Stemmer::new(Language::Spanish))
+}
+
+fn es_accented_analyzer(max_token_len: usize) -> TextAnalyzer {
+    TextAnalyzer::from(SimpleTokenizer)
+        .filter(RemoveLongFilter::limit(max_token_len))
+        .filter(LowerCaser)
+        .filter(StopWordFilter::new(Language::Spanish).unwrap())
+        .filter(Stemmer::new(Language::Spanish))
+}
+
+fn es_unstemmed_analyzer(max_token_len: usize) -> TextAnalyzer {
+    TextAnalyzer::from(SimpleTokenizer)
+        .filter(RemoveLongFilter::limit(max_token_len))
+        .filter(LowerCaser)
+        .filter(AsciiFoldingFilter)
+        .filter(StopWordFilter::new(Language::Spanish).unwrap())
+}
+
+fn exact_analyzer(max_token_len: usize) -> TextAnalyzer {
+    TextAnalyzer::from(SimpleTokenizer)
+        .filter(RemoveLongFilter::limit(max_token_len))
+        .filter(LowerCaser)
+}
+
+/// The simple tokenizer already splits on `@` and `.`, so `john.doe@gmail.com` is searchable
+/// by `john`, `doe` or `gmail` without the Spanish stemming mangling names and domains.
+fn email_analyzer(max_token_len: usize) -> TextAnalyzer {
+    TextAnalyzer::from(SimpleTokenizer)
+        .filter(RemoveLongFilter::limit(max_token_len))
+        .filter(LowerCaser)
+}
+
+/// Builds the `TextOptions` of a field indexed with the given analyzer.
+pub struct TextFieldOptions {
+    analyzer: Analyzer,
+    stored: bool,
+}
+
+impl TextFieldOptions {
+    pub fn new(analyzer: Analyzer) -> Self {
+        TextFieldOptions { analyzer, stored: false }
+    }
+
+    pub fn stored(mut self) -> Self {
+        self.stored = true;
+        self
+    }
+
+    pub fn build(self) -> TextOptions {
+        // Raw values are a single term, positions and frequencies are useless for them
+        let record = match self.analyzer {
+            Analyzer::Raw => IndexRecordOption::Basic,
+            _ => IndexRecordOption::WithFreqsAndPositions,
+        };
+        let text_field_indexing = TextFieldIndexing::default()
+            .set_tokenizer(self.analyzer.tokenizer_name())
+            .set_index_option(record);
+
+        let options = TextOptions::default().set_indexing_options(text_field_indexing);
+        if self.stored {
+            options.set_stored()
+        } else {
+            options
+        }
+    }
+}
+
+pub fn ngram2_options() -> TextOptions {
+    TextFieldOptions::new(Analyzer::Ngram2).stored().build()
 }
 
 pub fn field_to_string(doc: &Document, field: Field) -> String {
@@ -19,4 +208,91 @@ pub fn field_to_string(doc: &Document, field: Field) -> String {
         .map(|x| x.as_text().unwrap_or_default())
         .map(|x| x.to_string())
         .unwrap_or_default()
+}
+
+/// `None` when the document has no value for the field, which `field_to_string` can't tell
+/// from an empty one.
+pub fn field_to_optional_string(doc: &Document, field: Field) -> Option<String> {
+    doc.get_first(field)
+        .and_then(|x| x.as_text())
+        .map(|x| x.to_string())
+}
+
+pub fn field_to_u64(doc: &Document, field: Field) -> u64 {
+    doc.get_first(field)
+        .and_then(|x| x.as_u64())
+        .unwrap_or_default()
+}
+
+pub fn field_to_json(doc: &Document, field: Field) -> Value {
+    doc.get_first(field)
+        .and_then(|x| x.as_json())
+        .map(|x| Value::Object(x.clone()))
+        .unwrap_or_default()
+}
+
+pub fn field_to_facets(doc: &Document, field: Field) -> Vec<String> {
+    doc.get_all(field)
+        .filter_map(|x| x.as_facet())
+        .map(|x| x.to_path().join("/"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::indexation::{Analyzer, ANALYZERS, DEFAULT_MAX_TOKEN_LEN, email_analyzer, es_accented_analyzer, es_ngram2_analyzer, search_tokenizer_manager, tokenizer_manager};
+
+    #[test]
+    fn it_should_split_emails_on_at_and_dots_without_stemming() {
+        let mut tokens = Vec::new();
+        email_analyzer(DEFAULT_MAX_TOKEN_LEN).token_stream("Juanes.Garcia@Empleos.es").process(&mut |token| tokens.push(token.text.clone()));
+
+        assert_eq!(tokens, vec!["juanes", "garcia", "empleos", "es"]);
+    }
+
+    #[test]
+    fn it_should_index_the_prefixes_of_email_parts_but_not_search_them() {
+        let name = Analyzer::Email.tokenizer_name();
+        let mut indexed = Vec::new();
+        tokenizer_manager(DEFAULT_MAX_TOKEN_LEN).get(name).unwrap().token_stream("Joe@X.es").process(&mut |token| indexed.push((token.text.clone(), token.position)));
+        let mut searched = Vec::new();
+        search_tokenizer_manager(DEFAULT_MAX_TOKEN_LEN).get(name).unwrap().token_stream("Joe").process(&mut |token| searched.push(token.text.clone()));
+
+        let expected = [("j", 0), ("jo", 0), ("joe", 0), ("x", 1), ("e", 2), ("es", 2)];
+        assert_eq!(indexed, expected.map(|(text, position)| (String::from(text), position)));
+        assert_eq!(searched, vec!["joe"]);
+    }
+
+    #[test]
+    fn it_should_only_fold_accents_in_the_default_spanish_analyzer() {
+        for (analyzer, expected) in [(es_ngram2_analyzer(40), "canon"), (es_accented_analyzer(40), "cañon")] {
+            let mut tokens = Vec::new();
+            analyzer.token_stream("Cañón").process(&mut |token| tokens.push(token.text.clone()));
+
+            assert_eq!(tokens, vec![expected]);
+        }
+    }
+
+    #[test]
+    fn it_should_drop_tokens_longer_than_the_max_token_len() {
+        let long_token = "electroencefalografista".repeat(2);
+        for (max_token_len, dropped) in [(40, true), (50, false)] {
+            for analyzer in ANALYZERS {
+                let Some(text_analyzer) = analyzer.text_analyzer(max_token_len) else { continue };
+                let mut tokens = Vec::new();
+                text_analyzer.token_stream(&long_token).process(&mut |token| tokens.push(token.text.clone()));
+
+                assert_eq!(tokens.is_empty(), dropped, "{} with max token len {}", analyzer.tokenizer_name(), max_token_len);
+            }
+        }
+    }
+
+    #[test]
+    fn it_should_register_every_analyzer() {
+        for tokenizers in [tokenizer_manager(DEFAULT_MAX_TOKEN_LEN), search_tokenizer_manager(DEFAULT_MAX_TOKEN_LEN)] {
+            for analyzer in ANALYZERS {
+                assert!(tokenizers.get(analyzer.tokenizer_name()).is_some(), "{} is not registered", analyzer.tokenizer_name());
+            }
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+use tantivy::{DocAddress, DocId, DocSet, Postings, Score, Searcher, SegmentReader, TantivyError, Term};
+use tantivy::collector::{Collector, TopDocs};
+use tantivy::query::Query;
+use tantivy::schema::{Field, IndexRecordOption};
+
+const DEFAULT_K1: Score = 1.2;
+const DEFAULT_B: Score = 0.75;
+
+/// BM25 parameters taken from BM25_K1 and BM25_B. Tantivy hardcodes k1 = 1.2 and b = 0.75, so
+/// when any of them is set hits are rescored summing the BM25 of every query term with these
+/// parameters instead. The query still decides which documents match, but its boosts and
+/// phrase scoring are ignored, and so are terms it doesn't expose, like wildcard prefixes.
+#[derive(Clone, Copy, Debug)]
+pub struct Bm25 {
+    /// Term frequency saturation, the higher the more repeated terms keep adding to the score.
+    pub k1: Score,
+    /// Length normalization, from 0 where long documents aren't penalized at all to 1.
+    pub b: Score,
+}
+
+/// Multiplies the text relevance of every hit by the boosts set, none by default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ScoreBoost {
+    /// u64 fast field whose log2 multiplies the score.
+    pub field: Option<Field>,
+    pub recency: Option<Recency>,
+}
+
+/// Halves the score of a hit for every `half_life` elapsed since the epoch millis of its u64
+/// fast `field` until `now_millis`. Hits from the future aren't boosted further, and hits
+/// without a value, read as 0, aren't decayed.
+#[derive(Clone, Copy, Debug)]
+pub struct Recency {
+    pub field: Field,
+    pub half_life: Duration,
+    pub now_millis: u64,
+}
+
+impl ScoreBoost {
+    pub fn is_none(&self) -> bool {
+        self.field.is_none() && self.recency.is_none()
+    }
+
+    /// The boosted score of the hits of a segment given their unboosted one.
+    pub fn segment_boost(self, segment_reader: &SegmentReader) -> impl Fn(DocId, Score) -> Score {
+        let boost_reader = self.field.map(|field| segment_reader.fast_fields().u64(field).unwrap());
+        let recency = self.recency.map(|recency| {
+            let half_life = recency.half_life.as_millis().max(1) as Score;
+            (segment_reader.fast_fields().u64(recency.field).unwrap(), recency.now_millis, half_life)
+        });
+
+        move |doc: DocId, mut score: Score| {
+            if let Some(boost_reader) = &boost_reader {
+                score *= ((2u64 + boost_reader.get_val(doc)) as Score).log2();
+            }
+            if let Some((recency_reader, now_millis, half_life)) = &recency {
+                let millis = recency_reader.get_val(doc);
+                if millis > 0 {
+                    let age = now_millis.saturating_sub(millis) as Score;
+                    score *= 0.5f32.powf(age / half_life);
+                }
+            }
+            score
+        }
+    }
+}
+
+struct TermStats {
+    term: Term,
+    idf: Score,
+    average_fieldnorm: Score,
+}
+
+impl Bm25 {
+    /// `None` unless any parameter is given, the other one keeps tantivy's value.
+    pub fn new(k1: Option<Score>, b: Option<Score>) -> Option<Self> {
+        if k1.is_none() && b.is_none() {
+            return None;
+        }
+
+        Some(Bm25 { k1: k1.unwrap_or(DEFAULT_K1), b: b.unwrap_or(DEFAULT_B) })
+    }
+
+    /// Best hits first, their BM25 score boosted as `boost` says.
+    pub fn top_docs(self, searcher: &Searcher, query: &dyn Query, limit: usize, boost: ScoreBoost) -> Result<impl Collector<Fruit=Vec<(Score, DocAddress)>>, TantivyError> {
+        let mut terms: Vec<Term> = Vec::new();
+        query.query_terms(&mut |term, _| {
+            if !terms.contains(term) {
+                terms.push(term.clone());
+            }
+        });
+
+        // Same corpus statistics tantivy uses for its own BM25
+        let total_num_docs: u64 = searcher.segment_readers().iter().map(|s| u64::from(s.max_doc())).sum();
+        let mut stats = Vec::with_capacity(terms.len());
+        for term in terms {
+            let mut total_num_tokens = 0;
+            for segment_reader in searcher.segment_readers() {
+                total_num_tokens += segment_reader.inverted_index(term.field())?.total_num_tokens();
+            }
+            let doc_freq = searcher.doc_freq(&term)?;
+
+            stats.push(TermStats {
+                idf: idf(doc_freq, total_num_docs),
+                average_fieldnorm: total_num_tokens as Score / total_num_docs.max(1) as Score,
+                term,
+            });
+        }
+
+        let Bm25 { k1, b } = self;
+        Ok(TopDocs::with_limit(limit).custom_score(move |segment_reader: &SegmentReader| {
+            let mut term_scorers: Vec<_> = stats.iter()
+                .filter_map(|stats| {
+                    let inverted_index = segment_reader.inverted_index(stats.term.field()).ok()?;
+                    let postings = inverted_index.read_postings(&stats.term, IndexRecordOption::WithFreqs).ok()??;
+                    let fieldnorms = segment_reader.get_fieldnorms_reader(stats.term.field()).ok()?;
+                    Some((stats.idf, stats.average_fieldnorm, postings, fieldnorms))
+                })
+                .collect();
+            let segment_boost = boost.segment_boost(segment_reader);
+
+            // Matching docs come in increasing order, so postings only ever move forward
+            move |doc: DocId| {
+                let mut score: Score = 0.0;
+                for (idf, average_fieldnorm, postings, fieldnorms) in term_scorers.iter_mut() {
+                    let current = if postings.doc() < doc { postings.seek(doc) } else { postings.doc() };
+                    if current == doc {
+                        let tf = postings.term_freq() as Score;
+                        let norm = k1 * (1.0 - b + b * fieldnorms.fieldnorm(doc) as Score / *average_fieldnorm);
+                        score += *idf * tf * (k1 + 1.0) / (tf + norm);
+                    }
+                }
+
+                segment_boost(doc, score)
+            }
+        }))
+    }
+}
+
+fn idf(doc_freq: u64, total_num_docs: u64) -> Score {
+    let x = (total_num_docs.saturating_sub(doc_freq) as Score + 0.5) / (doc_freq as Score + 0.5);
+    (1.0 + x).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use tantivy::{doc, Index, Term};
+    use tantivy::query::TermQuery;
+    use tantivy::schema::{IndexRecordOption, Schema, TEXT};
+
+    use crate::indexation::scoring::{Bm25, ScoreBoost};
+
+    #[test]
+    fn it_should_stop_penalizing_long_documents_without_length_normalization() {
+        let mut schema_builder = Schema::builder();
+        let text = schema_builder.add_text_field("text", TEXT);
+        let index = Index::create_in_ram(schema_builder.build());
+
+        let mut writer = index.writer(15_000_000).unwrap();
+        writer.add_document(doc!(text => "caballo")).unwrap();
+        writer.add_document(doc!(text => "caballo caballo caballo perro gato vaca oveja cerdo gallina pato burro mula cabra toro liebre")).unwrap();
+        writer.commit().unwrap();
+
+        let searcher = index.reader().unwrap().searcher();
+        let query = TermQuery::new(Term::from_field_text(text, "caballo"), IndexRecordOption::WithFreqs);
+        let best_doc = |b| {
+            let collector = Bm25 { k1: 1.2, b }.top_docs(&searcher, &query, 2, ScoreBoost::default()).unwrap();
+            searcher.search(&query, &collector).unwrap()[0].1.doc_id
+        };
+
+        assert_eq!(best_doc(1.0), 0);
+        assert_eq!(best_doc(0.0), 1);
+    }
+}
\ No newline at end of file
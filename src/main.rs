@@ -1,33 +1,29 @@
 use std::env;
-use std::net::SocketAddr;
+use std::time::Duration;
 use tokio::signal;
 
+use axum_server::{AddrIncomingConfig, Handle, HttpConfig};
+use axum_server::tls_rustls::RustlsConfig;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry_otlp::WithExportConfig;
 use tracing_subscriber::EnvFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 
-use crate::server::new_router;
+use crate::config::Config;
+use crate::server::{AppState, new_state, routes};
 
+mod analyze;
+mod config;
+mod error;
 mod indexation;
+mod organization;
 mod person;
+mod query_log;
 mod question;
+mod search;
 mod server;
 
-#[derive(Debug, Clone)]
-pub struct AppEnv {
-    backend_env: String,
-}
-
-impl AppEnv {
-    fn new(backend_env: String) -> Self {
-        AppEnv {
-            backend_env
-        }
-    }
-
-    fn is_prod(&self) -> bool {
-        self.backend_env.eq_ignore_ascii_case("prod")
-    }
-}
-
 #[cfg(feature = "dhat-heap")]
 #[global_allocator]
 static ALLOC: dhat::Alloc = dhat::Alloc;
@@ -37,38 +33,119 @@ async fn main() {
     #[cfg(feature = "dhat-heap")]
         let _profiler = dhat::Profiler::new_heap();
 
-    let default_env = "development";
-    let backend_env = match env::var("BACKEND_SEARCH_ENV") {
-        Ok(env_var) => env_var,
-        Err(_) => String::from(default_env),
+    let config = match Config::from_env() {
+        Ok(c) => c,
+        Err(e) => panic!("{}", e)
     };
 
-    let app_env = AppEnv::new(backend_env);
-
-    if app_env.is_prod() {
+    if config.app_env.is_prod() {
         env::set_var("RUST_LOG", "info");
     } else {
         env::set_var("RUST_LOG", "tantivy_search=debug");
     }
 
-    // install global collector configured based on RUST_LOG env var. By default only logs WARN and up
-    tracing_subscriber::fmt()
-        .with_thread_ids(true)
-        .with_env_filter(EnvFilter::from_default_env())
-        .init();
+    init_tracing(&config);
+    let _query_log_guard = query_log::init(config.query_log_path.as_deref());
 
-    let app_router = match new_router(app_env).await {
-        Ok(r) => r,
+    let app_state = match new_state(&config).await {
+        Ok(s) => s,
         Err(e) => panic!("Error creating router: {:?}", e)
     };
+    let app_router = routes(app_state.clone());
+
+    let addr = config.bind_addr;
+
+    let handle = Handle::new();
+    tokio::spawn(graceful_shutdown(handle.clone(), app_state.clone(), config.shutdown_timeout));
+
+    // Connection tuning for the many small requests of the Go backend, both HTTP/1 and HTTP/2 are served
+    let incoming_config = AddrIncomingConfig::new()
+        .tcp_nodelay(config.tcp_nodelay)
+        .tcp_keepalive(config.tcp_keepalive)
+        .build();
+    let http_config = HttpConfig::new()
+        .http1_keep_alive(config.http1_keepalive)
+        .http2_keep_alive_interval(config.http2_keepalive_interval)
+        .build();
+
+    let service = app_router.into_make_service();
+    let served = match tls_config(&config).await {
+        Some(tls_config) => {
+            tracing::debug!("listening on https://{}", addr);
+            axum_server::bind_rustls(addr, tls_config)
+                .handle(handle)
+                .addr_incoming_config(incoming_config)
+                .http_config(http_config)
+                .serve(service)
+                .await
+        }
+        None => {
+            tracing::debug!("listening on {}", addr);
+            axum_server::bind(addr)
+                .handle(handle)
+                .addr_incoming_config(incoming_config)
+                .http_config(http_config)
+                .serve(service)
+                .await
+        }
+    };
+    served.unwrap();
+
+    // Commits what the drained requests indexed
+    app_state.flush().await;
+
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Installs the global collector configured based on RUST_LOG env var. By default only logs WARN and up.
+/// LOG_FORMAT=json switches to one json object per line for the log pipeline, keeping the
+/// human-oriented format otherwise.
+/// When OTEL_EXPORTER_OTLP_ENDPOINT is set, spans are also exported to that OTLP collector.
+fn init_tracing(config: &Config) {
+    let (json_layer, pretty_layer) = if config.log_json {
+        (Some(tracing_subscriber::fmt::layer().json().with_thread_ids(true)), None)
+    } else {
+        (None, Some(tracing_subscriber::fmt::layer().with_thread_ids(true)))
+    };
+
+    let registry = tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(json_layer)
+        .with(pretty_layer);
+
+    match &config.otlp_endpoint {
+        Some(endpoint) => {
+            opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint.as_str()))
+                .install_batch(opentelemetry::runtime::Tokio)
+                .expect("failed to install OTLP tracer");
+
+            registry
+                .with(tracing_opentelemetry::layer().with_tracer(tracer))
+                .init();
+        }
+        None => registry.init(),
+    }
+}
+
+/// TLS is served when both TLS_CERT_PATH and TLS_KEY_PATH point to PEM files, plain HTTP otherwise.
+async fn tls_config(config: &Config) -> Option<RustlsConfig> {
+    let (cert_path, key_path) = config.tls.as_ref()?;
+    match RustlsConfig::from_pem_file(cert_path, key_path).await {
+        Ok(config) => Some(config),
+        Err(e) => panic!("failed to load TLS certificate {} and key {}: {:?}", cert_path, key_path, e),
+    }
+}
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8079));
-    tracing::debug!("listening on {}", addr);
-    axum::Server::bind(&addr)
-        .serve(app_router.into_make_service())
-        .with_graceful_shutdown(shutdown_signal())
-        .await
-        .unwrap();
+/// Stops accepting connections and flushes the indexes as soon as the signal arrives, then
+/// serving ends once the in-flight requests are drained, or SHUTDOWN_TIMEOUT_SECS later closing
+/// the connections still open.
+async fn graceful_shutdown(handle: Handle, app_state: AppState, timeout: Option<Duration>) {
+    shutdown_signal().await;
+    handle.graceful_shutdown(timeout);
+    app_state.flush().await;
 }
 
 async fn shutdown_signal() {
@@ -0,0 +1,69 @@
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use tantivy::{doc, Document};
+
+use crate::organization::organization_fields;
+use crate::server::{AppState, CommitQuery, ValidJson, committed_response};
+
+#[derive(Deserialize)]
+pub struct IndexOrganization {
+    id: String,
+    name: String,
+    sector: String,
+    location: String,
+}
+
+#[derive(Deserialize)]
+pub struct ReIndexOrganization {
+    organizations: Vec<IndexOrganization>,
+}
+
+fn new_document(organization: &IndexOrganization) -> Document {
+    let fields = organization_fields();
+
+    doc!(
+        fields.id => organization.id.clone(),
+        fields.name => organization.name.clone(),
+        fields.sector => organization.sector.clone(),
+        fields.location => organization.location.clone())
+}
+
+pub async fn index_organization(State(state): State<AppState>, ValidJson(payload): ValidJson<IndexOrganization>) -> impl IntoResponse {
+    tracing::debug!("request received to index an organization, id: {}", payload.id);
+
+    match state.organization_index_handle.index_single(new_document(&payload)).await {
+        Ok(_) => StatusCode::ACCEPTED,
+        Err(e) => {
+            tracing::error!("failed to index organization {}: {:?}", payload.id, e);
+            e.status()
+        }
+    }
+}
+
+pub async fn delete_organization(State(state): State<AppState>, Path(organization_id): Path<String>) -> impl IntoResponse {
+    match state.organization_index_handle.delete(organization_id).await {
+        Ok(_) => StatusCode::ACCEPTED,
+        Err(e) => {
+            tracing::error!("failed to delete organization: {:?}", e);
+            e.status()
+        }
+    }
+}
+
+/// With `commit=true` answers once the whole batch is committed.
+pub async fn reindex_organization(State(state): State<AppState>, commit_query: Query<CommitQuery>, ValidJson(payload): ValidJson<ReIndexOrganization>) -> Response {
+    for o in payload.organizations {
+        if let Err(e) = state.organization_index_handle.index_single(new_document(&o)).await {
+            tracing::error!("failed to reindex organization {}: {:?}", o.id, e);
+            return e.status().into_response();
+        }
+    }
+
+    if commit_query.wait_for_commit() {
+        return committed_response(&state.organization_index_handle).await;
+    }
+
+    StatusCode::ACCEPTED.into_response()
+}
\ No newline at end of file
@@ -0,0 +1,66 @@
+use axum::Router;
+use axum::routing::{delete, get, post};
+use tantivy::schema::{Field, Schema, STORED, STRING};
+
+use crate::indexation::{Analyzer, IndexDefinition, ngram2_options, TextFieldOptions};
+use crate::organization::indexation::{delete_organization, index_organization, reindex_organization};
+use crate::organization::search::search_organizations;
+use crate::server::AppState;
+
+pub mod indexation;
+pub mod search;
+
+pub const ORGANIZATIONS_INDEX: &str = "organizations";
+
+/// Fields searched by unqualified query terms.
+pub const ORGANIZATION_DEFAULT_FIELDS: &[&str] = &["name", "location"];
+
+pub struct OrganizationFields {
+    id: Field,
+    name: Field,
+    sector: Field,
+    location: Field,
+}
+
+pub fn new_organization_schema() -> Schema {
+    let mut schema_builder = Schema::builder();
+
+    schema_builder.add_text_field("id", STRING | STORED);
+    schema_builder.add_text_field("name", ngram2_options());
+    schema_builder.add_text_field("sector", TextFieldOptions::new(Analyzer::Raw).stored().build());
+    schema_builder.add_text_field("location", ngram2_options());
+
+    schema_builder.build()
+}
+
+pub fn organization_index() -> IndexDefinition {
+    IndexDefinition {
+        name: ORGANIZATIONS_INDEX,
+        directory: "idx_organizations",
+        schema: new_organization_schema(),
+        schema_version: 1,
+        default_fields: ORGANIZATION_DEFAULT_FIELDS,
+        default_sort: None,
+        tie_breaker: Some("id"),
+        derive_fields: None,
+        expiry_field: None,
+    }
+}
+
+pub fn organization_routes() -> Router<AppState> {
+    Router::new()
+        .route("/organizations", get(search_organizations).post(index_organization))
+        .route("/organizations/reindex", post(reindex_organization))
+        .route("/organizations/:organization_id", delete(delete_organization))
+}
+
+pub fn organization_fields() -> OrganizationFields {
+    let schema = new_organization_schema();
+
+    OrganizationFields {
+        id: schema.get_field("id").unwrap(),
+        name: schema.get_field("name").unwrap(),
+        sector: schema.get_field("sector").unwrap(),
+        location: schema.get_field("location").unwrap(),
+    }
+}
\ No newline at end of file
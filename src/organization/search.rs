@@ -0,0 +1,73 @@
+use std::time::Instant;
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use tantivy::Score;
+
+use crate::indexation::{field_to_optional_string, field_to_string};
+use crate::indexation::handle::{SearchDocument, SearchOptions};
+use crate::organization::{ORGANIZATIONS_INDEX, organization_fields};
+use crate::query_log::log_query;
+use crate::server::{AppState, reindexing_response};
+
+#[derive(Deserialize)]
+pub struct SearchOrganizationQuery {
+    #[serde(default)]
+    query: String,
+    /// Hits answered, the index default one if none, between 1 and 100.
+    limit: Option<usize>,
+    /// Best hits skipped, to page through the results, at most 10000.
+    offset: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct SearchOrganizationResponse {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sector: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<String>,
+    score: Score,
+}
+
+pub async fn search_organizations(State(state): State<AppState>, search_query: Query<SearchOrganizationQuery>) -> Response {
+    if state.organization_index_handle.is_reindexing() {
+        return reindexing_response(&state);
+    }
+
+    let _permit = match state.search_permit() {
+        Ok(permit) => permit,
+        Err(e) => return e.into_response(),
+    };
+
+    let limit = state.organization_index_handle.limit(search_query.limit);
+    let options = SearchOptions { offset: search_query.offset, ..SearchOptions::default() };
+    let started = Instant::now();
+    let search_result = state.organization_index_handle.search(search_query.query.as_str(), limit, options).await;
+
+    match search_result {
+        Ok(organization_docs) => {
+            log_query(ORGANIZATIONS_INDEX, &search_query.query, organization_docs.len(), started.elapsed());
+            let response: Vec<SearchOrganizationResponse> = organization_docs.iter().map(document_to_organization).collect();
+            (StatusCode::OK, Json(response)).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+fn document_to_organization(sdoc: &SearchDocument) -> SearchOrganizationResponse {
+    let fields = organization_fields();
+
+    SearchOrganizationResponse {
+        id: field_to_string(&sdoc.doc, fields.id),
+        name: field_to_optional_string(&sdoc.doc, fields.name),
+        sector: field_to_optional_string(&sdoc.doc, fields.sector),
+        location: field_to_optional_string(&sdoc.doc, fields.location),
+        score: sdoc.score,
+    }
+}
\ No newline at end of file
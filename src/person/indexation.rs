@@ -1,12 +1,14 @@
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::Json;
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use serde::Deserialize;
+use serde_json::json;
 use tantivy::{doc, Document};
+use tantivy::schema::Field;
 
 use crate::person::person_fields;
-use crate::server::AppState;
+use crate::server::{AppState, CommitQuery, ValidJson, committed_response};
 
 #[derive(Deserialize)]
 pub struct IndexPerson {
@@ -14,6 +16,12 @@ pub struct IndexPerson {
     email: String,
 }
 
+/// The new email of a person, the id is taken from the path.
+#[derive(Deserialize)]
+pub struct PatchPerson {
+    email: String,
+}
+
 #[derive(Deserialize)]
 pub struct ReIndexPerson {
     people: Vec<IndexPerson>,
@@ -22,28 +30,93 @@ pub struct ReIndexPerson {
 fn new_document(person: &IndexPerson) -> Document {
     let fields = person_fields();
 
-    doc!(
+    let mut doc = doc!(
         fields.id => person.id.clone(),
-        fields.email => person.email.clone())
+        fields.email => person.email.clone());
+
+    // People without a valid email are still indexed, they just can't be found by domain
+    if let Some(domain) = email_domain(&person.email) {
+        doc.add_text(fields.email_domain, domain);
+    }
+
+    doc
+}
+
+/// The email along with the domain derived from it, which is not stored so a patch can't keep
+/// the old one.
+fn patch_document(patch: &PatchPerson) -> (Vec<Field>, Document) {
+    let fields = person_fields();
+    let mut doc = doc!(fields.email => patch.email.clone());
+    if let Some(domain) = email_domain(&patch.email) {
+        doc.add_text(fields.email_domain, domain);
+    }
+
+    (vec![fields.email, fields.email_domain], doc)
+}
+
+fn email_domain(email: &str) -> Option<String> {
+    email.rsplit_once('@').and_then(|(_, domain)| normalize_domain(domain))
+}
+
+/// Domains are indexed untokenized, so they're matched in lowercase.
+pub fn normalize_domain(domain: &str) -> Option<String> {
+    Some(domain.trim().to_lowercase()).filter(|domain| !domain.is_empty())
 }
 
-pub async fn index_person(State(state): State<AppState>, Json(payload): Json<IndexPerson>) -> impl IntoResponse {
+pub async fn index_person(State(state): State<AppState>, ValidJson(payload): ValidJson<IndexPerson>) -> impl IntoResponse {
     tracing::debug!("request received to index a person, id: {}", payload.id);
 
-    state.person_index_handle.index_single(new_document(&payload)).await;
+    match state.person_index_handle.index_single(new_document(&payload)).await {
+        Ok(_) => StatusCode::ACCEPTED,
+        Err(e) => {
+            tracing::error!("failed to index person {}: {:?}", payload.id, e);
+            e.status()
+        }
+    }
+}
+
+pub async fn patch_person(State(state): State<AppState>, Path(person_id): Path<String>, ValidJson(payload): ValidJson<PatchPerson>) -> impl IntoResponse {
+    let (fields, doc) = patch_document(&payload);
 
-    StatusCode::ACCEPTED
+    match state.person_index_handle.patch(person_id.clone(), fields, doc).await {
+        Ok(true) => StatusCode::ACCEPTED,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            tracing::error!("failed to patch person {}: {:?}", person_id, e);
+            e.status()
+        }
+    }
 }
 
 pub async fn delete_person(State(state): State<AppState>, Path(person_id): Path<String>) -> impl IntoResponse {
-    state.person_index_handle.delete(person_id).await;
-    StatusCode::ACCEPTED
+    match state.person_index_handle.delete(person_id).await {
+        Ok(_) => StatusCode::ACCEPTED,
+        Err(e) => {
+            tracing::error!("failed to delete person: {:?}", e);
+            e.status()
+        }
+    }
 }
 
-pub async fn reindex_person(State(state): State<AppState>, Json(payload): Json<ReIndexPerson>) -> impl IntoResponse {
+/// With `commit=true` answers once the whole batch is committed.
+pub async fn reindex_person(State(state): State<AppState>, commit_query: Query<CommitQuery>, ValidJson(payload): ValidJson<ReIndexPerson>) -> Response {
     for p in payload.people {
-        state.person_index_handle.index_single(new_document(&p)).await;
+        if let Err(e) = state.person_index_handle.index_single(new_document(&p)).await {
+            tracing::error!("failed to reindex person {}: {:?}", p.id, e);
+            return e.status().into_response();
+        }
+    }
+
+    if commit_query.wait_for_commit() {
+        return committed_response(&state.person_index_handle).await;
     }
 
-    StatusCode::ACCEPTED
+    StatusCode::ACCEPTED.into_response()
+}
+
+pub async fn reload_person_reader(State(state): State<AppState>) -> impl IntoResponse {
+    match state.person_index_handle.reload() {
+        Ok(reader) => (StatusCode::OK, Json(json!({ "generation": reader.generation, "opstamp": reader.opstamp }))).into_response(),
+        Err(e) => e.into_response(),
+    }
 }
\ No newline at end of file
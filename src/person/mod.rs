@@ -1,31 +1,73 @@
+use axum::Router;
+use axum::routing::{delete, get, post};
 use tantivy::schema::{Field, Schema, STORED, STRING};
 
-use crate::indexation::ngram2_options;
+use crate::indexation::{Analyzer, IndexDefinition, TextFieldOptions};
+use crate::person::indexation::{delete_person, index_person, patch_person, reindex_person, reload_person_reader};
+use crate::person::search::{count_people, export_people, person_exists, person_schema, person_stats, search_people, validate_person_query};
+use crate::server::AppState;
 
 pub mod indexation;
 pub mod search;
 
+pub const PEOPLE_INDEX: &str = "people";
+
+/// Fields searched by unqualified query terms.
+pub const PERSON_DEFAULT_FIELDS: &[&str] = &["email"];
+
 pub struct PersonFields {
     id: Field,
     email: Field,
+    email_domain: Field,
 }
 
 pub fn new_person_schema() -> Schema {
     let mut schema_builder = Schema::builder();
 
     schema_builder.add_text_field("id", STRING | STORED);
-    schema_builder.add_text_field("email", ngram2_options());
+    schema_builder.add_text_field("email", TextFieldOptions::new(Analyzer::Email).stored().build());
+    // Lowercased part after the `@`, only indexed to filter people by exact domain
+    schema_builder.add_text_field("email_domain", STRING);
 
     schema_builder.build()
 }
 
+pub fn person_index() -> IndexDefinition {
+    IndexDefinition {
+        name: PEOPLE_INDEX,
+        directory: "idx_people",
+        schema: new_person_schema(),
+        schema_version: 1,
+        default_fields: PERSON_DEFAULT_FIELDS,
+        default_sort: None,
+        tie_breaker: Some("id"),
+        derive_fields: None,
+        expiry_field: None,
+    }
+}
+
+pub fn person_routes() -> Router<AppState> {
+    Router::new()
+        .route("/people", get(search_people).post(index_person))
+        .route("/people/count", get(count_people))
+        .route("/people/export", get(export_people))
+        .route("/people/reader/reload", post(reload_person_reader))
+        .route("/people/reindex", post(reindex_person))
+        .route("/people/schema", get(person_schema))
+        .route("/people/stats", get(person_stats))
+        .route("/people/validate", get(validate_person_query))
+        .route("/people/:person_id", delete(delete_person).head(person_exists).patch(patch_person))
+}
+
 pub fn person_fields() -> PersonFields {
     let schema = new_person_schema();
     let id_field = schema.get_field("id").unwrap();
     let email_field = schema.get_field("email").unwrap();
+    let email_domain_field = schema.get_field("email_domain").unwrap();
 
     PersonFields {
         id: id_field,
         email: email_field,
+        email_domain: email_domain_field,
     }
 }
\ No newline at end of file
@@ -1,48 +1,125 @@
-use axum::extract::{Query, State};
+use std::time::Instant;
+
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::Json;
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use serde::{Deserialize, Serialize};
-use tantivy::Score;
+use serde_json::json;
+use tantivy::{Score, Term};
 
-use crate::indexation::field_to_string;
-use crate::indexation::handle::SearchDocument;
-use crate::person::person_fields;
-use crate::server::AppState;
+use crate::indexation::{field_to_optional_string, field_to_string};
+use crate::indexation::handle::{SearchDocument, SearchOptions};
+use crate::person::indexation::normalize_domain;
+use crate::person::{PEOPLE_INDEX, person_fields};
+use crate::query_log::log_query;
+use crate::server::{AppState, ndjson_response, reindexing_response, stats_response};
 
 #[derive(Deserialize)]
 pub struct SearchPersonQuery {
+    #[serde(default)]
     query: String,
+    /// Only people whose email is at this domain, like `example.com`.
+    domain: Option<String>,
+    /// Hits answered, the index default one if none, between 1 and 100.
+    limit: Option<usize>,
+    /// Best hits skipped, to page through the results, at most 10000.
+    offset: Option<usize>,
 }
 
 #[derive(Serialize)]
-struct SearchPersonResponse {
+pub struct SearchPersonResponse {
     id: String,
-    email: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
     score: Score,
 }
 
-pub async fn search_people(State(state): State<AppState>, search_query: Query<SearchPersonQuery>) -> impl IntoResponse {
-    let search_result = state.person_index_handle.search(search_query.query.as_str(), 10).await;
+pub async fn search_people(State(state): State<AppState>, search_query: Query<SearchPersonQuery>) -> Response {
+    if state.person_index_handle.is_reindexing() {
+        return reindexing_response(&state);
+    }
+
+    let _permit = match state.search_permit() {
+        Ok(permit) => permit,
+        Err(e) => return e.into_response(),
+    };
+
+    let limit = state.person_index_handle.limit(search_query.limit);
+    let mut options = SearchOptions { offset: search_query.offset, ..SearchOptions::default() };
+    if let Some(domain) = search_query.domain.as_deref().and_then(normalize_domain) {
+        options.filters.push(Term::from_field_text(person_fields().email_domain, domain.as_str()));
+    }
+
+    let started = Instant::now();
+    let search_result = state.person_index_handle.search(search_query.query.as_str(), limit, options).await;
 
     match search_result {
         Ok(people_docs) => {
+            log_query(PEOPLE_INDEX, &search_query.query, people_docs.len(), started.elapsed());
             let response: Vec<SearchPersonResponse> = people_docs.iter().map(document_to_person).collect();
-            (StatusCode::OK, Json(response))
-        }
-        Err(e) => {
-            tracing::error!("failed to search people: {:?}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(vec![]))
+            (StatusCode::OK, Json(response)).into_response()
         }
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Streams every person as one JSON object per line, for migrations. An empty index answers
+/// an empty body.
+pub async fn export_people(State(state): State<AppState>) -> Response {
+    if state.person_index_handle.is_reindexing() {
+        return reindexing_response(&state);
+    }
+
+    ndjson_response(state.person_index_handle.export(), document_to_person)
+}
+
+/// 200 when a person has the id, 404 otherwise, without a body.
+pub async fn person_exists(State(state): State<AppState>, Path(person_id): Path<String>) -> StatusCode {
+    match state.person_index_handle.exists(&person_id).await {
+        Ok(true) => StatusCode::OK,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => e.status(),
+    }
+}
+
+/// Counts the people matching the query, cheaper than a search as none is retrieved.
+pub async fn count_people(State(state): State<AppState>, search_query: Query<SearchPersonQuery>) -> impl IntoResponse {
+    let mut options = SearchOptions::default();
+    if let Some(domain) = search_query.domain.as_deref().and_then(normalize_domain) {
+        options.filters.push(Term::from_field_text(person_fields().email_domain, domain.as_str()));
     }
+
+    match state.person_index_handle.count(search_query.query.as_str(), options).await {
+        Ok(count) => (StatusCode::OK, Json(json!({ "count": count }))).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// How many people are searched and deleted but still stored, and in how many segments.
+pub async fn person_stats(State(state): State<AppState>) -> Response {
+    stats_response(&state.person_index_handle)
+}
+
+/// Checks the query syntax without searching, answering how it was parsed.
+pub async fn validate_person_query(State(state): State<AppState>, search_query: Query<SearchPersonQuery>) -> impl IntoResponse {
+    match state.person_index_handle.validate(search_query.query.as_str()) {
+        Ok(parsed) => (StatusCode::OK, Json(json!({ "query": parsed }))).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Field names, types and indexing options of the person index.
+pub async fn person_schema(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.person_index_handle.schema().clone())
 }
 
-fn document_to_person(sdoc: &SearchDocument) -> SearchPersonResponse {
+pub fn document_to_person(sdoc: &SearchDocument) -> SearchPersonResponse {
     let fields = person_fields();
 
     SearchPersonResponse {
         id: field_to_string(&sdoc.doc, fields.id),
-        email: field_to_string(&sdoc.doc, fields.email),
+        email: field_to_optional_string(&sdoc.doc, fields.email),
         score: sdoc.score,
     }
 }
\ No newline at end of file
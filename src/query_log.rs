@@ -0,0 +1,76 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+
+static QUERY_LOG: OnceLock<NonBlocking> = OnceLock::new();
+
+/// Opens the QUERY_LOG_PATH file for appending, when given, to log every search query as one json
+/// object per line, apart from the tracing logs. Lines are written from a background thread,
+/// dropped if it falls behind, and the pending ones are flushed when the guard is dropped.
+pub fn init(path: Option<&str>) -> Option<WorkerGuard> {
+    let path = path?;
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .unwrap_or_else(|e| panic!("failed to open query log {}: {:?}", path, e));
+
+    let (writer, guard) = tracing_appender::non_blocking(file);
+    QUERY_LOG.set(writer).ok()?;
+
+    Some(guard)
+}
+
+/// Logs a successful search of the index, doing nothing unless the query log is enabled.
+pub fn log_query(index: &str, query: &str, hits: usize, took: Duration) {
+    let Some(writer) = QUERY_LOG.get() else {
+        return;
+    };
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+    if let Err(e) = writer.clone().write_all(query_line(index, query, hits, took, timestamp).as_bytes()) {
+        tracing::warn!("failed to log {} query: {:?}", index, e);
+    }
+}
+
+fn query_line(index: &str, query: &str, hits: usize, took: Duration, timestamp: u64) -> String {
+    let line = json!({
+        "index": index,
+        "query": query,
+        "hits": hits,
+        "took_ms": took.as_millis() as u64,
+        "timestamp": timestamp,
+    });
+
+    format!("{}\n", line)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::{json, Value};
+
+    use crate::query_log::query_line;
+
+    #[test]
+    fn it_should_write_one_json_object_per_line() {
+        let line = query_line("questions", "caballo \"blanco\"", 3, Duration::from_micros(12_500), 1_700_000_000_000);
+
+        assert!(line.ends_with('\n'));
+        assert_eq!(line.matches('\n').count(), 1);
+
+        let logged: Value = serde_json::from_str(line.trim_end()).unwrap();
+        assert_eq!(logged, json!({
+            "index": "questions",
+            "query": "caballo \"blanco\"",
+            "hits": 3,
+            "took_ms": 12,
+            "timestamp": 1_700_000_000_000u64,
+        }));
+    }
+}
\ No newline at end of file
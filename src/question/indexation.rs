@@ -1,20 +1,64 @@
-use axum::extract::{Path, State};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::Json;
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use serde::Deserialize;
+use serde_json::{json, Value};
 use tantivy::{doc, Document};
+use tantivy::schema::{Facet, Field, FieldValue};
+use tantivy::time::format_description::well_known::Rfc3339;
+use tantivy::time::OffsetDateTime;
+use uuid::Uuid;
+use whatlang::{Detector, Lang};
 
+use crate::indexation::{field_to_string, field_to_u64, normalize_id};
+use crate::config::ServerConfig;
 use crate::question::question_fields;
-use crate::server::AppState;
+use crate::server::{AppState, CommitQuery, ValidJson, committed_response};
 
 #[derive(Deserialize)]
 pub struct IndexQuestion {
+    /// A new UUID when the client leaves it to the server, stored and indexed like a given one.
+    #[serde(default = "new_id")]
     pub id: String,
     pub question: String,
     pub public_employment_name: String,
     pub question_type: String,
     pub created_at: String,
+    #[serde(default)]
+    pub popularity: u64,
+    #[serde(default)]
+    pub metadata: Value,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Epoch millis after which the question is deleted, it never expires if missing.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    /// ISO 639-1 code of the question language, like `en`, detected from the text if missing.
+    #[serde(default)]
+    pub lang: Option<String>,
+}
+
+/// Only the given fields replace the stored ones, the id is taken from the path.
+#[derive(Default, Deserialize)]
+pub struct PatchQuestion {
+    pub question: Option<String>,
+    pub public_employment_name: Option<String>,
+    pub question_type: Option<String>,
+    pub created_at: Option<String>,
+    pub popularity: Option<u64>,
+    pub metadata: Option<Value>,
+    pub tags: Option<Vec<String>>,
+    pub expires_at: Option<u64>,
+    /// Replaces the language, otherwise detected again from a patched question text.
+    pub lang: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteQuestionBatch {
+    ids: Vec<String>,
 }
 
 #[derive(Deserialize)]
@@ -22,34 +66,320 @@ pub struct ReIndexQuestion {
     questions: Vec<IndexQuestion>,
 }
 
-pub fn new_document(question: &IndexQuestion) -> Document {
+fn new_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+/// Languages of the corpus, detecting among these only is faster and more accurate.
+const DETECTED_LANGS: [(Lang, &str); 2] = [(Lang::Spa, "es"), (Lang::Eng, "en")];
+
+/// ISO 639-1 code of the text language, `None` when the detection is unsure or disabled with
+/// LANG_DETECTION=false. Every language still goes through the same Spanish analyzer, using
+/// another one per language would need a text field per language.
+fn detect_lang(text: &str, config: &ServerConfig) -> Option<&'static str> {
+    if !config.lang_detection {
+        return None;
+    }
+
+    let detector = Detector::with_allowlist(DETECTED_LANGS.iter().map(|(lang, _)| *lang).collect());
+    detector.detect(text)
+        .filter(|info| info.is_reliable())
+        .and_then(|info| DETECTED_LANGS.iter().find(|(lang, _)| *lang == info.lang()))
+        .map(|(_, code)| *code)
+}
+
+/// The given language lowercased, or the one detected from the text, DEFAULT_LANG when the
+/// detection is unsure or disabled. `None` if there's no default either.
+fn question_lang(lang: Option<&str>, text: &str, config: &ServerConfig) -> Option<String> {
+    lang.map(|lang| lang.to_ascii_lowercase())
+        .or_else(|| detect_lang(text, config).map(String::from))
+        .or_else(|| config.default_lang.clone())
+}
+
+/// ISO 639-1 codes have two letters, anything else given as a language is rejected.
+pub fn is_valid_lang(lang: Option<&str>) -> bool {
+    lang.map(|lang| lang.len() == 2 && lang.chars().all(|c| c.is_ascii_alphabetic())).unwrap_or(true)
+}
+
+pub fn new_document(question: &IndexQuestion, config: &ServerConfig) -> Document {
     let fields = question_fields();
 
-    doc!(
+    let mut doc = doc!(
         fields.id => question.id.clone(),
         fields.question => question.question.clone(),
+        fields.question_raw => question.question.clone(),
+        fields.question_accented => question.question.clone(),
+        fields.question_unstemmed => question.question.clone(),
         fields.public_employment_name => question.public_employment_name.clone(),
         fields.question_type => question.question_type.clone(),
         fields.created_at => question.created_at.clone(),
-    )
+        fields.popularity => question.popularity,
+        fields.rank => question.popularity,
+        fields.updated_at => now_millis(),
+    );
+
+    if let Some(created_at_millis) = created_at_millis(&question.created_at) {
+        doc.add_u64(fields.created_at_millis, created_at_millis);
+    }
+
+    // Only json objects can be stored in a tantivy json field
+    if let Value::Object(metadata) = &question.metadata {
+        doc.add_json_object(fields.metadata, metadata.clone());
+    }
+
+    doc.add_facet(fields.question_type_facet, Facet::from_path(vec![&question.question_type]));
+    for tag in &question.tags {
+        doc.add_facet(fields.tags, Facet::from_path(vec![tag]));
+    }
+
+    if let Some(lang) = question_lang(question.lang.as_deref(), &question.question, config) {
+        doc.add_text(fields.lang, lang);
+    }
+
+    if let Some(expires_at) = question.expires_at {
+        doc.add_u64(fields.expires_at, expires_at);
+    }
+
+    doc
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// Epoch millis of an RFC 3339 `created_at` like `2023-04-01T10:00:00Z`, `None` for any other
+/// format or a date before the epoch.
+fn created_at_millis(created_at: &str) -> Option<u64> {
+    let created_at = OffsetDateTime::parse(created_at, &Rfc3339).ok()?;
+    u64::try_from(created_at.unix_timestamp_nanos() / 1_000_000).ok()
+}
+
+/// Rebuilds the indexed only fields of a question, the exact, accented and unstemmed texts, the
+/// rank and the creation millis, from its stored question text, popularity and `created_at`.
+pub fn derive_fields(doc: Document) -> Document {
+    let fields = question_fields();
+    let derived = [fields.question_raw, fields.question_accented, fields.question_unstemmed, fields.rank, fields.created_at_millis];
+    let question = field_to_string(&doc, fields.question);
+    let popularity = field_to_u64(&doc, fields.popularity);
+    let created_at_millis = created_at_millis(&field_to_string(&doc, fields.created_at));
+
+    let mut doc: Document = doc.into_iter()
+        .filter(|fv| !derived.contains(&fv.field()))
+        .collect::<Vec<FieldValue>>()
+        .into();
+    doc.add_text(fields.question_raw, &question);
+    doc.add_text(fields.question_accented, &question);
+    doc.add_text(fields.question_unstemmed, &question);
+    doc.add_u64(fields.rank, popularity);
+    if let Some(created_at_millis) = created_at_millis {
+        doc.add_u64(fields.created_at_millis, created_at_millis);
+    }
+
+    doc
+}
+
+/// The patched fields along with a document holding their new values.
+pub fn patch_document(patch: &PatchQuestion, config: &ServerConfig) -> (Vec<Field>, Document) {
+    let fields = question_fields();
+    let mut patched = vec![fields.updated_at];
+    let mut doc = doc!(fields.updated_at => now_millis());
+
+    let texts = [
+        (fields.question, &patch.question),
+        (fields.public_employment_name, &patch.public_employment_name),
+        (fields.question_type, &patch.question_type),
+        (fields.created_at, &patch.created_at),
+    ];
+    for (field, value) in texts {
+        if let Some(value) = value {
+            patched.push(field);
+            doc.add_text(field, value);
+        }
+    }
+
+    if let Some(question_type) = &patch.question_type {
+        patched.push(fields.question_type_facet);
+        doc.add_facet(fields.question_type_facet, Facet::from_path(vec![question_type]));
+    }
+
+    // The language follows the question text unless given, the indexed only fields are
+    // derived again by `derive_fields`
+    if patch.question.is_some() || patch.lang.is_some() {
+        patched.push(fields.lang);
+        if let Some(lang) = question_lang(patch.lang.as_deref(), patch.question.as_deref().unwrap_or_default(), config) {
+            doc.add_text(fields.lang, lang);
+        }
+    }
+
+    if let Some(popularity) = patch.popularity {
+        patched.push(fields.popularity);
+        doc.add_u64(fields.popularity, popularity);
+    }
+
+    if let Some(expires_at) = patch.expires_at {
+        patched.push(fields.expires_at);
+        doc.add_u64(fields.expires_at, expires_at);
+    }
+
+    if let Some(metadata) = &patch.metadata {
+        patched.push(fields.metadata);
+        if let Value::Object(metadata) = metadata {
+            doc.add_json_object(fields.metadata, metadata.clone());
+        }
+    }
+
+    if let Some(tags) = &patch.tags {
+        patched.push(fields.tags);
+        for tag in tags {
+            doc.add_facet(fields.tags, Facet::from_path(vec![tag]));
+        }
+    }
+
+    (patched, doc)
+}
+
+/// The 400 answering a question text longer than MAX_QUESTION_LEN characters, none if it's
+/// unbounded. Such questions are rejected rather than indexed with their long tokens silently
+/// dropped.
+fn too_long_response(question: &str, max_len: Option<usize>) -> Option<Response> {
+    let max_len = max_len.filter(|max_len| question.chars().count() > *max_len)?;
+    let message = format!("question is longer than {} characters", max_len);
+    Some((StatusCode::BAD_REQUEST, Json(json!({ "error": message }))).into_response())
+}
+
+/// The 400 answering a given language that isn't an ISO 639-1 code, none if it is one or if no
+/// language is given.
+fn invalid_lang_response(lang: Option<&str>) -> Option<Response> {
+    let lang = lang.filter(|lang| !is_valid_lang(Some(lang)))?;
+    let message = format!("lang must be an ISO 639-1 code, got: {}", lang);
+    Some((StatusCode::BAD_REQUEST, Json(json!({ "error": message }))).into_response())
 }
 
-pub async fn index_question(State(state): State<AppState>, Json(payload): Json<IndexQuestion>) -> impl IntoResponse {
+/// With `commit=true` answers a 200 once the question is committed, searchable as soon as
+/// the reader reloads on that commit, instead of a 202 once it's queued.
+pub async fn index_question(State(state): State<AppState>, commit_query: Query<CommitQuery>, ValidJson(payload): ValidJson<IndexQuestion>) -> Response {
     tracing::debug!("request received to index a question id: {}, question: {}", payload.id, payload.question);
 
-    state.question_index_handle.index_single(new_document(&payload)).await;
+    if let Some(response) = too_long_response(&payload.question, state.config.max_question_len) {
+        tracing::debug!("question {} is too long to be indexed", payload.id);
+        return response;
+    }
+
+    if let Some(response) = invalid_lang_response(payload.lang.as_deref()) {
+        tracing::debug!("question {} language is not an ISO 639-1 code", payload.id);
+        return response;
+    }
+
+    if let Err(e) = state.question_index_handle.index_single(new_document(&payload, &state.config)).await {
+        tracing::error!("failed to index question {}: {:?}", payload.id, e);
+        return e.status().into_response();
+    }
+
+    if !commit_query.wait_for_commit() {
+        return (StatusCode::ACCEPTED, Json(json!({ "id": normalize_id(&payload.id) }))).into_response();
+    }
+
+    // The actor handles its messages in order, so the flush commits the question sent above
+    match state.question_index_handle.flush().await {
+        Ok(opstamp) => (StatusCode::OK, Json(json!({ "id": normalize_id(&payload.id), "opstamp": opstamp }))).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+pub async fn patch_question(State(state): State<AppState>, Path(question_id): Path<String>, ValidJson(payload): ValidJson<PatchQuestion>) -> Response {
+    if let Some(response) = payload.question.as_deref().and_then(|question| too_long_response(question, state.config.max_question_len)) {
+        tracing::debug!("question {} is too long to be indexed", question_id);
+        return response;
+    }
+
+    if let Some(response) = invalid_lang_response(payload.lang.as_deref()) {
+        tracing::debug!("question {} language is not an ISO 639-1 code", question_id);
+        return response;
+    }
+
+    let (fields, doc) = patch_document(&payload, &state.config);
 
-    StatusCode::ACCEPTED
+    match state.question_index_handle.patch(question_id.clone(), fields, doc).await {
+        Ok(true) => StatusCode::ACCEPTED.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("failed to patch question {}: {:?}", question_id, e);
+            e.status().into_response()
+        }
+    }
 }
 
 pub async fn delete_question(State(state): State<AppState>, Path(question_id): Path<String>) -> impl IntoResponse {
-    state.question_index_handle.delete(question_id).await;
-    StatusCode::ACCEPTED
+    match state.question_index_handle.delete(question_id).await {
+        Ok(_) => StatusCode::ACCEPTED,
+        Err(e) => {
+            tracing::error!("failed to delete question: {:?}", e);
+            e.status()
+        }
+    }
+}
+
+pub async fn delete_question_batch(State(state): State<AppState>, ValidJson(payload): ValidJson<DeleteQuestionBatch>) -> impl IntoResponse {
+    match state.question_index_handle.delete_batch(payload.ids).await {
+        Ok(accepted) => (StatusCode::ACCEPTED, Json(json!({ "accepted": accepted }))).into_response(),
+        Err(e) => e.into_response(),
+    }
 }
 
-pub async fn reindex_question(State(state): State<AppState>, Json(payload): Json<ReIndexQuestion>) -> impl IntoResponse {
+/// With `commit=true` answers once the whole batch is committed.
+pub async fn reindex_question(State(state): State<AppState>, commit_query: Query<CommitQuery>, ValidJson(payload): ValidJson<ReIndexQuestion>) -> Response {
+    // Checked upfront so a rejected batch leaves the index untouched
+    for q in &payload.questions {
+        if let Some(response) = too_long_response(&q.question, state.config.max_question_len) {
+            tracing::debug!("question {} is too long to be reindexed", q.id);
+            return response;
+        }
+    }
+
+    for q in &payload.questions {
+        if let Some(response) = invalid_lang_response(q.lang.as_deref()) {
+            tracing::debug!("question {} language is not an ISO 639-1 code", q.id);
+            return response;
+        }
+    }
+
     for q in payload.questions {
-        state.question_index_handle.index_single(new_document(&q)).await;
+        if let Err(e) = state.question_index_handle.index_single(new_document(&q, &state.config)).await {
+            tracing::error!("failed to reindex question {}: {:?}", q.id, e);
+            return e.status().into_response();
+        }
+    }
+
+    if commit_query.wait_for_commit() {
+        return committed_response(&state.question_index_handle).await;
+    }
+
+    StatusCode::ACCEPTED.into_response()
+}
+
+/// Unlike `reindex_question`, which ingests the given questions, this asks the Go backend
+/// to send all of them again.
+pub async fn trigger_reindex_question(State(state): State<AppState>) -> impl IntoResponse {
+    match state.question_index_handle.reindex().await {
+        Ok(status) => status,
+        Err(e) => {
+            tracing::error!("failed to trigger questions reindex: {:?}", e);
+            e.status()
+        }
+    }
+}
+
+pub async fn reload_question_reader(State(state): State<AppState>) -> impl IntoResponse {
+    match state.question_index_handle.reload() {
+        Ok(reader) => (StatusCode::OK, Json(json!({ "generation": reader.generation, "opstamp": reader.opstamp }))).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Merges every question segment into one, meant to be run off-peak.
+pub async fn merge_questions(State(state): State<AppState>) -> impl IntoResponse {
+    match state.question_index_handle.merge().await {
+        Ok(segments) => (StatusCode::OK, Json(json!({ "segments": segments }))).into_response(),
+        Err(e) => e.into_response(),
     }
-    StatusCode::ACCEPTED
 }
\ No newline at end of file
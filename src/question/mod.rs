@@ -1,16 +1,39 @@
-use tantivy::schema::{Field, Schema, STORED, STRING};
+use axum::Router;
+use axum::routing::{delete, get, post};
+use tantivy::schema::{FAST, FacetOptions, Field, INDEXED, Schema, STORED, STRING, TEXT};
 
-use crate::indexation::ngram2_options;
+use crate::indexation::{Analyzer, IndexDefinition, ngram2_options, TextFieldOptions};
+use crate::indexation::handle::{Sort, SortOrder};
+use crate::question::indexation::{delete_question, delete_question_batch, derive_fields, index_question, merge_questions, patch_question, reindex_question, reload_question_reader, trigger_reindex_question};
+use crate::question::search::{autocomplete_questions, count_questions, explain_question, export_questions, facet_questions, mget_questions, question_exists, question_schema, question_stats, question_types, search_questions, validate_question_query};
+use crate::server::AppState;
 
 pub mod indexation;
 pub mod search;
 
+pub const QUESTIONS_INDEX: &str = "questions";
+
+/// Fields searched by unqualified query terms.
+pub const QUESTION_DEFAULT_FIELDS: &[&str] = &["question"];
+
 pub struct QuestionFields {
     id: Field,
     question: Field,
+    question_raw: Field,
+    question_accented: Field,
+    question_unstemmed: Field,
     public_employment_name: Field,
     question_type: Field,
+    question_type_facet: Field,
     created_at: Field,
+    created_at_millis: Field,
+    popularity: Field,
+    rank: Field,
+    metadata: Field,
+    tags: Field,
+    lang: Field,
+    updated_at: Field,
+    expires_at: Field,
 }
 
 pub fn new_question_schema() -> Schema {
@@ -20,52 +43,173 @@ pub fn new_question_schema() -> Schema {
 
     schema_builder.add_text_field("id", STRING | STORED);
     schema_builder.add_text_field("question", text_options);
-    schema_builder.add_text_field("public_employment_name", STORED);
-    schema_builder.add_text_field("question_type", STORED);
+    // Same text without stemming, searched by `match=exact`
+    schema_builder.add_text_field("question_raw", TextFieldOptions::new(Analyzer::Exact).build());
+    // Same text stemmed but keeping its accents, searched by `accent_sensitive=true`
+    schema_builder.add_text_field("question_accented", TextFieldOptions::new(Analyzer::Ngram2Accented).build());
+    // Same text without accents nor stop words but not stemmed, searched by `stemming=false`
+    schema_builder.add_text_field("question_unstemmed", TextFieldOptions::new(Analyzer::Ngram2Unstemmed).build());
+    // Only searched by qualified terms, like `public_employment_name:policia`, and highlighted
+    schema_builder.add_text_field("public_employment_name", ngram2_options());
+    schema_builder.add_text_field("question_type", STRING | STORED);
+    // Same type as a facet, counted over the matching questions by `facet=question_type`. Stored
+    // so patches keep it, they rebuild the document from its stored fields
+    schema_builder.add_facet_field("question_type_facet", FacetOptions::default().set_stored());
     schema_builder.add_text_field("created_at", STORED);
+    // Epoch millis of an RFC 3339 `created_at`, the age decayed by `recency_boost=true`. Missing
+    // for any other format
+    schema_builder.add_u64_field("created_at_millis", FAST);
+    schema_builder.add_u64_field("popularity", FAST | STORED);
+    // Precomputed static score ordering `approximate=true` searches, the popularity for now, kept
+    // apart from it so its formula can evolve
+    schema_builder.add_u64_field("rank", FAST);
+    // Free-form client metadata, searchable through its json paths, e.g. `metadata.topic:history`
+    schema_builder.add_json_field("metadata", TEXT | STORED);
+    schema_builder.add_facet_field("tags", FacetOptions::default().set_stored());
+    // ISO 639-1 code given when indexing or detected from the question text, DEFAULT_LANG or
+    // missing when the detection is unsure
+    schema_builder.add_text_field("lang", STRING | STORED);
+    // Epoch millis of the last index or patch of the question, the order of question browsing
+    schema_builder.add_u64_field("updated_at", FAST | STORED);
+    // Epoch millis after which the question is deleted by the expiry sweep, missing if it never
+    // expires
+    schema_builder.add_u64_field("expires_at", INDEXED | STORED);
 
     schema_builder.build()
 }
 
+pub fn question_index() -> IndexDefinition {
+    let schema = new_question_schema();
+
+    IndexDefinition {
+        name: QUESTIONS_INDEX,
+        directory: "idx_questions",
+        default_fields: QUESTION_DEFAULT_FIELDS,
+        default_sort: Some(Sort { field: schema.get_field("updated_at").unwrap(), order: SortOrder::Desc }),
+        tie_breaker: Some("id"),
+        derive_fields: Some(derive_fields),
+        expiry_field: Some("expires_at"),
+        schema_version: 4,
+        schema,
+    }
+}
+
+pub fn question_routes() -> Router<AppState> {
+    Router::new()
+        .route("/questions", get(search_questions).post(index_question))
+        .route("/questions/autocomplete", get(autocomplete_questions))
+        .route("/questions/count", get(count_questions))
+        .route("/questions/explain", get(explain_question))
+        .route("/questions/export", get(export_questions))
+        .route("/questions/facets", get(facet_questions))
+        .route("/questions/types", get(question_types))
+        .route("/questions/schema", get(question_schema))
+        .route("/questions/stats", get(question_stats))
+        .route("/questions/validate", get(validate_question_query))
+        .route("/questions/delete-batch", post(delete_question_batch))
+        .route("/questions/merge", post(merge_questions))
+        .route("/questions/mget", post(mget_questions))
+        .route("/questions/reader/reload", post(reload_question_reader))
+        .route("/questions/reindex", post(reindex_question))
+        .route("/questions/reindex/trigger", post(trigger_reindex_question))
+        .route("/questions/:question_id", delete(delete_question).head(question_exists).patch(patch_question))
+}
+
 pub fn question_fields() -> QuestionFields {
     let schema = new_question_schema();
     let id = schema.get_field("id").unwrap();
     let question = schema.get_field("question").unwrap();
+    let question_raw = schema.get_field("question_raw").unwrap();
+    let question_accented = schema.get_field("question_accented").unwrap();
+    let question_unstemmed = schema.get_field("question_unstemmed").unwrap();
     let public_employment_name = schema.get_field("public_employment_name").unwrap();
     let question_type = schema.get_field("question_type").unwrap();
+    let question_type_facet = schema.get_field("question_type_facet").unwrap();
     let created_at = schema.get_field("created_at").unwrap();
+    let created_at_millis = schema.get_field("created_at_millis").unwrap();
+    let popularity = schema.get_field("popularity").unwrap();
+    let rank = schema.get_field("rank").unwrap();
+    let metadata = schema.get_field("metadata").unwrap();
+    let tags = schema.get_field("tags").unwrap();
+    let lang = schema.get_field("lang").unwrap();
+    let updated_at = schema.get_field("updated_at").unwrap();
+    let expires_at = schema.get_field("expires_at").unwrap();
 
     QuestionFields {
         id,
         question,
+        question_raw,
+        question_accented,
+        question_unstemmed,
         public_employment_name,
         question_type,
+        question_type_facet,
         created_at,
+        created_at_millis,
+        popularity,
+        rank,
+        metadata,
+        tags,
+        lang,
+        updated_at,
+        expires_at,
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use tantivy::directory::RamDirectory;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use serde_json::{json, Value};
+    use tantivy::{doc, Document, Index, IndexSettings, Term};
+    use tantivy::directory::{MmapDirectory, RamDirectory};
+    use tantivy::schema::{FieldType, FieldValue};
+    use tantivy::time::format_description::well_known::Rfc3339;
+    use tantivy::time::OffsetDateTime;
     use tracing_subscriber::EnvFilter;
     use uuid::Uuid;
 
-    use crate::AppEnv;
-    use crate::indexation::handle::IndexActorHandle;
-    use crate::question::indexation::{IndexQuestion, new_document};
-    use crate::question::new_question_schema;
+    use crate::config::{IndexerConfig, ServerConfig};
+    use crate::error::SearchError;
+    use crate::indexation::{Bm25, field_to_string, field_to_u64};
+    use crate::indexation::cache::QueryCache;
+    use crate::indexation::handle::{IndexActorHandle, MAX_SEARCH_LIMIT, SearchDocument, SearchOptions, SnippetOptions, Sort, SortOrder};
+    use crate::question::indexation::{IndexQuestion, new_document, patch_document, PatchQuestion};
+    use crate::person::new_person_schema;
+    use crate::question::{new_question_schema, question_fields, question_index};
+    use crate::question::search::document_to_question;
 
     async fn new_question_index_handle() -> IndexActorHandle {
         let dir = RamDirectory::create();
-        IndexActorHandle::new(dir, new_question_schema(), String::from("test"), AppEnv::new("dev".to_string())).await.unwrap()
+        IndexActorHandle::new(dir, question_index(), IndexerConfig::default()).await.unwrap()
     }
 
-    #[tokio::test]
-    async fn it_should_index_a_single_question() {
-        tracing_subscriber::fmt()
+    fn init_tracing() {
+        let _ = tracing_subscriber::fmt()
             .with_thread_ids(true)
             .with_env_filter(EnvFilter::from_default_env())
-            .init();
+            .try_init();
+    }
+
+    fn new_question(question: &str) -> IndexQuestion {
+        IndexQuestion {
+            id: Uuid::new_v4().to_string(),
+            question: String::from(question),
+            public_employment_name: "Public Employment".to_string(),
+            question_type: "ADMINISTRATION".to_string(),
+            created_at: "asd".to_string(),
+            popularity: 0,
+            metadata: Value::Null,
+            tags: vec![],
+            expires_at: None,
+            lang: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_index_a_single_question() {
+        init_tracing();
         let question_index_handle = new_question_index_handle().await;
         let question_to_index = IndexQuestion {
             id: Uuid::new_v4().to_string(),
@@ -73,20 +217,886 @@ mod tests {
             public_employment_name: "Public Employment".to_string(),
             question_type: "ADMINISTRATION".to_string(),
             created_at: "asd".to_string(),
+            popularity: 0,
+            metadata: Value::Null,
+            tags: vec![],
+            expires_at: None,
+            lang: None,
         };
 
         // Index a question
-        question_index_handle.index_single(new_document(&question_to_index)).await;
+        question_index_handle.index_single(new_document(&question_to_index, &ServerConfig::default())).await.unwrap();
 
         // Search by 'caballo', should be a spawn to not block the thread of the test and to wait until the question is indexed.
         let search_query = "caballo";
-        let mut result = question_index_handle.search(search_query, 10).await.unwrap();
+        let mut result = question_index_handle.search(search_query, 10, SearchOptions::default()).await.unwrap();
 
         while result.is_empty() {
             question_index_handle.commit(String::from("test")).await;
-            result = question_index_handle.search(search_query, 10).await.unwrap();
+            result = question_index_handle.search(search_query, 10, SearchOptions::default()).await.unwrap();
         }
 
         assert_eq!(result.len(), 1);
     }
+
+    #[tokio::test]
+    async fn it_should_rank_popular_questions_first_when_boosting() {
+        init_tracing();
+        let question_index_handle = new_question_index_handle().await;
+        let unpopular = new_question("Había una vez un caballo blanco");
+        let mut popular = new_question("Había una vez un caballo negro");
+        popular.popularity = 1000;
+
+        question_index_handle.index_single(new_document(&unpopular, &ServerConfig::default())).await.unwrap();
+        question_index_handle.index_single(new_document(&popular, &ServerConfig::default())).await.unwrap();
+
+        let search_query = "caballo";
+        let mut result = question_index_handle.search(search_query, 10, SearchOptions::default()).await.unwrap();
+
+        while result.len() < 2 {
+            question_index_handle.commit(String::from("test")).await;
+            result = question_index_handle.search(search_query, 10, SearchOptions::default()).await.unwrap();
+        }
+
+        let options = SearchOptions { boost_field: Some(question_fields().popularity), ..Default::default() };
+        let boosted = question_index_handle.search(search_query, 10, options).await.unwrap();
+
+        assert_eq!(boosted.len(), 2);
+        assert_eq!(field_to_string(&boosted[0].doc, question_fields().id), popular.id);
+        assert!(boosted[0].score > boosted[1].score);
+    }
+
+    #[tokio::test]
+    async fn it_should_rank_recent_questions_first_when_boosting_recency() {
+        init_tracing();
+        let fields = question_fields();
+        let bm25 = IndexerConfig { bm25: Bm25::new(Some(1.2), None), ..IndexerConfig::default() };
+        for config in [IndexerConfig::default(), bm25] {
+            let index_handle = IndexActorHandle::new(RamDirectory::create(), question_index(), config).await.unwrap();
+            let now = OffsetDateTime::now_utc();
+            let mut old = new_question("Había una vez un caballo blanco");
+            let mut recent = new_question("Había una vez un caballo blanco");
+
+            // Two half lives older than the recent one, both indexed now
+            old.created_at = (now - 2 * IndexerConfig::default().recency_half_life).format(&Rfc3339).unwrap();
+            recent.created_at = now.format(&Rfc3339).unwrap();
+            index_handle.index_single(new_document(&old, &ServerConfig::default())).await.unwrap();
+            index_handle.index_single(new_document(&recent, &ServerConfig::default())).await.unwrap();
+            while index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().len() < 2 {
+                index_handle.commit(String::from("test")).await;
+            }
+
+            let unboosted = index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap();
+            assert_eq!(unboosted[0].score, unboosted[1].score);
+
+            let options = SearchOptions { recency_field: Some(fields.created_at_millis), ..SearchOptions::default() };
+            let boosted = index_handle.search("caballo", 10, options.clone()).await.unwrap();
+            assert_eq!(field_to_string(&boosted[0].doc, fields.id), recent.id);
+            assert!((boosted[1].score - unboosted[1].score / 4.0).abs() < 1e-3, "{} is not a quarter of {}", boosted[1].score, unboosted[1].score);
+
+            // Patching the old question doesn't make it recent
+            let (patched, doc) = patch_document(&PatchQuestion { popularity: Some(1), ..PatchQuestion::default() }, &ServerConfig::default());
+            assert!(index_handle.patch(old.id.clone(), patched, doc).await.unwrap());
+            while index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().iter().all(|hit| field_to_u64(&hit.doc, fields.popularity) == 0) {
+                index_handle.commit(String::from("test")).await;
+            }
+            let boosted = index_handle.search("caballo", 10, options).await.unwrap();
+            assert_eq!(field_to_string(&boosted[0].doc, fields.id), recent.id);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_store_and_search_by_metadata() {
+        init_tracing();
+        let question_index_handle = new_question_index_handle().await;
+        let mut question = new_question("Había una vez un caballo blanco");
+        question.metadata = json!({"topic": "history", "difficulty": 3});
+
+        question_index_handle.index_single(new_document(&question, &ServerConfig::default())).await.unwrap();
+
+        let search_query = "metadata.topic:history";
+        let mut result = question_index_handle.search(search_query, 10, SearchOptions::default()).await.unwrap();
+
+        while result.is_empty() {
+            question_index_handle.commit(String::from("test")).await;
+            result = question_index_handle.search(search_query, 10, SearchOptions::default()).await.unwrap();
+        }
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(document_to_question(&result[0]).metadata, question.metadata);
+    }
+
+    #[test]
+    fn it_should_leave_the_missing_fields_out_of_a_question() {
+        let fields = question_fields();
+        let doc = doc!(fields.id => "1", fields.question => "", fields.question_type => "LAW");
+        let sdoc = SearchDocument { doc, score: 1.0, snippets: Default::default(), doc_address: None };
+
+        let question = serde_json::to_value(document_to_question(&sdoc)).unwrap();
+        assert_eq!(question["question"], "");
+        assert_eq!(question["question_type"], "LAW");
+        for missing in ["public_employment_name", "created_at", "lang"] {
+            assert!(question.get(missing).is_none(), "{} should be missing", missing);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_count_questions_per_tag() {
+        init_tracing();
+        let question_index_handle = new_question_index_handle().await;
+        let mut first = new_question("Había una vez un caballo blanco");
+        first.tags = vec![String::from("animals"), String::from("colors")];
+        let mut second = new_question("Había una vez un caballo negro");
+        second.tags = vec![String::from("animals")];
+
+        question_index_handle.index_single(new_document(&first, &ServerConfig::default())).await.unwrap();
+        question_index_handle.index_single(new_document(&second, &ServerConfig::default())).await.unwrap();
+
+        let tags = question_fields().tags;
+        let mut counts = question_index_handle.facet_counts("", &SearchOptions::default(), tags).await.unwrap();
+
+        while counts.get("animals") != Some(&2) {
+            question_index_handle.commit(String::from("test")).await;
+            counts = question_index_handle.facet_counts("", &SearchOptions::default(), tags).await.unwrap();
+        }
+
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts.get("colors"), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn it_should_filter_questions_by_type() {
+        init_tracing();
+        let question_index_handle = new_question_index_handle().await;
+        let question = new_question("Había una vez un caballo blanco");
+
+        question_index_handle.index_single(new_document(&question, &ServerConfig::default())).await.unwrap();
+
+        let search_query = "caballo";
+        let question_type = question_fields().question_type;
+        let filter_by = |value: &str| SearchOptions { filters: vec![Term::from_field_text(question_type, value)], ..Default::default() };
+        let mut result = question_index_handle.search(search_query, 10, filter_by("ADMINISTRATION")).await.unwrap();
+
+        while result.is_empty() {
+            question_index_handle.commit(String::from("test")).await;
+            result = question_index_handle.search(search_query, 10, filter_by("ADMINISTRATION")).await.unwrap();
+        }
+
+        assert_eq!(result.len(), 1);
+
+        let result = question_index_handle.search(search_query, 10, filter_by("NON_EXISTENT")).await.unwrap();
+
+        assert!(result.is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_delete_a_question_with_a_differently_cased_id() {
+        init_tracing();
+        let question_index_handle = new_question_index_handle().await;
+        let mut question = new_question("Había una vez un caballo blanco");
+        question.id = question.id.to_uppercase();
+
+        question_index_handle.index_single(new_document(&question, &ServerConfig::default())).await.unwrap();
+
+        let search_query = "caballo";
+        let mut result = question_index_handle.search(search_query, 10, SearchOptions::default()).await.unwrap();
+
+        while result.is_empty() {
+            question_index_handle.commit(String::from("test")).await;
+            result = question_index_handle.search(search_query, 10, SearchOptions::default()).await.unwrap();
+        }
+
+        assert_eq!(field_to_string(&result[0].doc, question_fields().id), question.id.to_lowercase());
+
+        question_index_handle.delete(question.id.to_lowercase()).await.unwrap();
+
+        while !result.is_empty() {
+            question_index_handle.commit(String::from("test")).await;
+            result = question_index_handle.search(search_query, 10, SearchOptions::default()).await.unwrap();
+        }
+
+        assert!(result.is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_count_questions_per_distinct_type() {
+        init_tracing();
+        let question_index_handle = new_question_index_handle().await;
+        let mut law = new_question("Había una vez un juez");
+        law.question_type = String::from("LAW");
+
+        question_index_handle.index_single(new_document(&new_question("Había una vez un caballo blanco"), &ServerConfig::default())).await.unwrap();
+        question_index_handle.index_single(new_document(&new_question("Había una vez un caballo negro"), &ServerConfig::default())).await.unwrap();
+        question_index_handle.index_single(new_document(&law, &ServerConfig::default())).await.unwrap();
+
+        let question_type = question_fields().question_type;
+        let mut counts = question_index_handle.term_counts(question_type).await.unwrap();
+
+        while counts.values().sum::<u64>() < 3 {
+            question_index_handle.commit(String::from("test")).await;
+            counts = question_index_handle.term_counts(question_type).await.unwrap();
+        }
+
+        assert_eq!(counts.into_iter().collect::<Vec<_>>(), vec![(String::from("ADMINISTRATION"), 2), (String::from("LAW"), 1)]);
+    }
+
+    #[tokio::test]
+    async fn it_should_only_search_default_fields_for_unqualified_terms() {
+        init_tracing();
+        let question_index_handle = new_question_index_handle().await;
+
+        question_index_handle.index_single(new_document(&new_question("Había una vez un caballo blanco"), &ServerConfig::default())).await.unwrap();
+
+        let mut result = question_index_handle.search("question_type:ADMINISTRATION", 10, SearchOptions::default()).await.unwrap();
+
+        while result.is_empty() {
+            question_index_handle.commit(String::from("test")).await;
+            result = question_index_handle.search("question_type:ADMINISTRATION", 10, SearchOptions::default()).await.unwrap();
+        }
+
+        let result = question_index_handle.search("ADMINISTRATION", 10, SearchOptions::default()).await.unwrap();
+
+        assert!(result.is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_merge_all_segments_into_one() {
+        init_tracing();
+        let question_index_handle = new_question_index_handle().await;
+
+        for _ in 0..3 {
+            question_index_handle.index_single(new_document(&new_question("Había una vez un caballo blanco"), &ServerConfig::default())).await.unwrap();
+            question_index_handle.commit(String::from("test")).await;
+        }
+
+        assert_eq!(question_index_handle.merge().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_should_collapse_the_same_id_found_in_two_segments() {
+        init_tracing();
+        let dir = RamDirectory::create();
+        let fields = question_fields();
+
+        // Bypasses the actor delete-then-add so both segments keep a doc with the same id
+        let index = Index::create(dir.clone(), question_index().schema, IndexSettings::default()).unwrap();
+        let mut writer = index.writer(15_000_000).unwrap();
+        for _ in 0..2 {
+            writer.add_document(doc!(fields.id => "1", fields.question_type => "ADMINISTRATION")).unwrap();
+            writer.commit().unwrap();
+        }
+        drop(writer);
+        assert_eq!(index.searchable_segment_ids().unwrap().len(), 2);
+
+        let handle = IndexActorHandle::new(dir, question_index(), IndexerConfig::default()).await.unwrap();
+        let docs = handle.search("", 10, SearchOptions::default()).await.unwrap();
+
+        assert_eq!(docs.len(), 1);
+        assert_eq!(field_to_string(&docs[0].doc, fields.id), "1");
+    }
+
+    #[tokio::test]
+    async fn it_should_answer_flushes_once_committed() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+        index_handle.index_single(new_document(&new_question("Había una vez un caballo blanco"), &ServerConfig::default())).await.unwrap();
+
+        index_handle.flush().await.unwrap();
+        index_handle.reload().unwrap();
+
+        assert_eq!(index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_should_only_replace_the_patched_fields() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+        let question = new_question("Había una vez un caballo blanco");
+
+        index_handle.index_single(new_document(&question, &ServerConfig::default())).await.unwrap();
+
+        let patch = PatchQuestion { question_type: Some(String::from("LAW")), popularity: Some(3), ..PatchQuestion::default() };
+        let (fields, doc) = patch_document(&patch, &ServerConfig::default());
+        assert!(index_handle.patch(question.id.clone(), fields, doc).await.unwrap());
+
+        let mut docs = index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap();
+        while docs.is_empty() || field_to_u64(&docs[0].doc, question_fields().popularity) != 3 {
+            index_handle.commit(String::from("test")).await;
+            docs = index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap();
+        }
+
+        let fields = question_fields();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(field_to_string(&docs[0].doc, fields.question), "Había una vez un caballo blanco");
+        assert_eq!(field_to_string(&docs[0].doc, fields.question_type), "LAW");
+
+        // The indexed only fields are derived again from the stored ones
+        let exact = SearchOptions { default_fields: Some(vec![fields.question_raw]), ..SearchOptions::default() };
+        assert_eq!(index_handle.search("caballo", 10, exact).await.unwrap().len(), 1);
+        let ranked = SearchOptions { rank_field: Some(fields.rank), ..SearchOptions::default() };
+        assert_eq!(index_handle.search("caballo", 10, ranked).await.unwrap()[0].score, 3.0);
+
+        let (fields, doc) = patch_document(&patch, &ServerConfig::default());
+        assert!(!index_handle.patch(String::from("missing"), fields, doc).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn it_should_highlight_every_matching_snippet_field() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+        let mut question = new_question("Había una vez un caballo blanco que cruzaba un río muy caudaloso y frío");
+        question.public_employment_name = String::from("Policía Nacional");
+
+        index_handle.index_single(new_document(&question, &ServerConfig::default())).await.unwrap();
+
+        let fields = question_fields();
+        let options = |max_chars| SearchOptions {
+            stored_fields: Some(vec![fields.id]),
+            snippets: Some(SnippetOptions { fields: vec![(fields.question, fields.question), (fields.public_employment_name, fields.public_employment_name)], max_chars }),
+            ..SearchOptions::default()
+        };
+        let mut docs = index_handle.search("caballo", 10, options(150)).await.unwrap();
+        while docs.is_empty() {
+            index_handle.commit(String::from("test")).await;
+            docs = index_handle.search("caballo", 10, options(150)).await.unwrap();
+        }
+
+        assert_eq!(docs[0].snippets.keys().collect::<Vec<_>>(), vec!["question"]);
+        assert_eq!(docs[0].snippets["question"], "Había una vez un <b>caballo</b> blanco que cruzaba un río muy caudaloso y frío");
+
+        let docs = index_handle.search("caballo public_employment_name:policia", 10, options(20)).await.unwrap();
+        assert_eq!(docs[0].snippets["public_employment_name"], "<b>Policía</b> Nacional");
+        assert!(docs[0].snippets["question"].chars().count() <= 20 + "<b></b>".len());
+    }
+
+    #[tokio::test]
+    async fn it_should_only_retrieve_the_requested_stored_fields() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+        let mut question = new_question("Había una vez un caballo blanco");
+        question.metadata = json!({"source": "exam"});
+
+        index_handle.index_single(new_document(&question, &ServerConfig::default())).await.unwrap();
+
+        let fields = question_fields();
+        let options = || SearchOptions { stored_fields: Some(vec![fields.id]), ..SearchOptions::default() };
+        let mut docs = index_handle.search("caballo", 10, options()).await.unwrap();
+        while docs.is_empty() {
+            index_handle.commit(String::from("test")).await;
+            docs = index_handle.search("caballo", 10, options()).await.unwrap();
+        }
+
+        assert_eq!(field_to_string(&docs[0].doc, fields.id), question.id);
+        assert_eq!(docs[0].doc.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_should_drop_hits_below_the_min_score() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+        let strong = new_question("caballo blanco caballo");
+        let weak = new_question("un caballo entre muchas otras palabras que no tienen nada que ver");
+
+        index_handle.index_single(new_document(&strong, &ServerConfig::default())).await.unwrap();
+        index_handle.index_single(new_document(&weak, &ServerConfig::default())).await.unwrap();
+
+        let mut docs = index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap();
+        while docs.len() < 2 {
+            index_handle.commit(String::from("test")).await;
+            docs = index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap();
+        }
+        assert!(docs[0].score > docs[1].score);
+
+        let min_score = (docs[0].score + docs[1].score) / 2.0;
+        let options = SearchOptions { min_score: Some(min_score), ..SearchOptions::default() };
+        let docs = index_handle.search("caballo", 10, options).await.unwrap();
+
+        assert_eq!(docs.len(), 1);
+        assert_eq!(field_to_string(&docs[0].doc, question_fields().id), strong.id);
+    }
+
+    #[tokio::test]
+    async fn it_should_expand_trailing_wildcards_and_reject_leading_ones() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+        let question = new_question("Había una vez un caballo blanco");
+
+        index_handle.index_single(new_document(&question, &ServerConfig::default())).await.unwrap();
+
+        let mut docs = index_handle.search("Caba*", 10, SearchOptions::default()).await.unwrap();
+        while docs.is_empty() {
+            index_handle.commit(String::from("test")).await;
+            docs = index_handle.search("Caba*", 10, SearchOptions::default()).await.unwrap();
+        }
+        assert_eq!(field_to_string(&docs[0].doc, question_fields().id), question.id);
+
+        let prefix = &question.id[..8];
+        let docs = index_handle.search(format!("id:{}*", prefix).as_str(), 10, SearchOptions::default()).await.unwrap();
+        assert_eq!(docs.len(), 1);
+
+        assert!(index_handle.search("*allo", 10, SearchOptions::default()).await.is_err());
+        assert!(index_handle.search("negro", 10, SearchOptions::default()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_match_ids_exactly_without_analyzing_them() {
+        init_tracing();
+        let schema = question_index().schema;
+        let id_indexing = match schema.get_field_entry(question_fields().id).field_type() {
+            FieldType::Str(options) => options.get_indexing_options().unwrap().clone(),
+            _ => panic!("id must be a text field"),
+        };
+        assert_eq!(id_indexing.tokenizer(), "raw");
+
+        let index_handle = new_question_index_handle().await;
+        let mut question = new_question("Había una vez un caballo blanco");
+        question.id = String::from("Exam-2023/01:A");
+
+        index_handle.index_single(new_document(&question, &ServerConfig::default())).await.unwrap();
+
+        let mut docs = index_handle.search("id:EXAM-2023/01:a", 10, SearchOptions::default()).await.unwrap();
+        while docs.is_empty() {
+            index_handle.commit(String::from("test")).await;
+            docs = index_handle.search("id:EXAM-2023/01:a", 10, SearchOptions::default()).await.unwrap();
+        }
+
+        assert_eq!(docs.len(), 1);
+        assert_eq!(field_to_string(&docs[0].doc, question_fields().id), "exam-2023/01:a");
+    }
+
+    #[tokio::test]
+    async fn it_should_filter_questions_by_detected_language() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+        let spanish = new_question("¿Cuál es el órgano encargado de aprobar los presupuestos generales del Estado?");
+        let english = new_question("Which body is in charge of approving the general state budget every year?");
+
+        index_handle.index_single(new_document(&spanish, &ServerConfig::default())).await.unwrap();
+        index_handle.index_single(new_document(&english, &ServerConfig::default())).await.unwrap();
+
+        while index_handle.search("", 10, SearchOptions::default()).await.unwrap().len() < 2 {
+            index_handle.commit(String::from("test")).await;
+        }
+
+        let fields = question_fields();
+        for (lang, question) in [("es", &spanish), ("en", &english)] {
+            let options = SearchOptions { filters: vec![Term::from_field_text(fields.lang, lang)], ..SearchOptions::default() };
+            let docs = index_handle.search("", 10, options).await.unwrap();
+
+            assert_eq!(docs.len(), 1);
+            assert_eq!(field_to_string(&docs[0].doc, fields.id), question.id);
+        }
+    }
+
+    #[test]
+    fn it_should_index_the_given_language_over_the_detected_one() {
+        let fields = question_fields();
+        let mut question = new_question("¿Cuál es el órgano encargado de aprobar los presupuestos generales del Estado?");
+        assert_eq!(field_to_string(&new_document(&question, &ServerConfig::default()), fields.lang), "es");
+
+        let undetected = ServerConfig { lang_detection: false, ..ServerConfig::default() };
+        assert!(new_document(&question, &undetected).get_first(fields.lang).is_none());
+        let defaulted = ServerConfig { default_lang: Some(String::from("gl")), ..undetected.clone() };
+        assert_eq!(field_to_string(&new_document(&question, &defaulted), fields.lang), "gl");
+
+        question.lang = Some(String::from("EN"));
+        assert_eq!(field_to_string(&new_document(&question, &undetected), fields.lang), "en");
+
+        let patch = PatchQuestion { lang: Some(String::from("fr")), ..PatchQuestion::default() };
+        let (patched, doc) = patch_document(&patch, &ServerConfig::default());
+        assert!(patched.contains(&fields.lang));
+        assert_eq!(field_to_string(&doc, fields.lang), "fr");
+    }
+
+    #[tokio::test]
+    async fn it_should_delete_a_batch_of_questions() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+        let questions: Vec<IndexQuestion> = (0..3).map(|_| new_question("Había una vez un caballo blanco")).collect();
+
+        for question in &questions {
+            index_handle.index_single(new_document(question, &ServerConfig::default())).await.unwrap();
+        }
+        while index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().len() < 3 {
+            index_handle.commit(String::from("test")).await;
+        }
+
+        let ids = vec![questions[0].id.to_uppercase(), questions[1].id.clone()];
+        assert_eq!(index_handle.delete_batch(ids).await.unwrap(), 2);
+
+        let mut docs = index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap();
+        while docs.len() > 1 {
+            index_handle.commit(String::from("test")).await;
+            docs = index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap();
+        }
+        assert_eq!(field_to_string(&docs[0].doc, question_fields().id), questions[2].id);
+    }
+
+    #[tokio::test]
+    async fn it_should_browse_the_last_updated_questions_first() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+        let older = new_question("Había una vez un caballo blanco");
+        let newer = new_question("Había una vez un caballo negro");
+
+        index_handle.index_single(new_document(&older, &ServerConfig::default())).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        index_handle.index_single(new_document(&newer, &ServerConfig::default())).await.unwrap();
+
+        let mut docs = index_handle.search("", 10, SearchOptions::default()).await.unwrap();
+        while docs.len() < 2 {
+            index_handle.commit(String::from("test")).await;
+            docs = index_handle.search("", 10, SearchOptions::default()).await.unwrap();
+        }
+
+        let fields = question_fields();
+        assert_eq!(field_to_string(&docs[0].doc, fields.id), newer.id);
+
+        let sort = Sort { field: fields.updated_at, order: SortOrder::Asc };
+        let docs = index_handle.search("", 10, SearchOptions { sort: Some(sort), ..SearchOptions::default() }).await.unwrap();
+        assert_eq!(field_to_string(&docs[0].doc, fields.id), older.id);
+    }
+
+    #[tokio::test]
+    async fn it_should_break_sort_ties_by_id() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+        let fields = question_fields();
+
+        for (id, updated_at) in [("c", 1000), ("z", 2000), ("b", 1000), ("a", 1000)] {
+            let mut question = new_question("Había una vez un caballo blanco");
+            question.id = String::from(id);
+            let mut doc: Document = new_document(&question, &ServerConfig::default()).into_iter()
+                .filter(|fv| fv.field() != fields.updated_at)
+                .collect::<Vec<FieldValue>>()
+                .into();
+            doc.add_u64(fields.updated_at, updated_at);
+            index_handle.index_single(doc).await.unwrap();
+            index_handle.flush().await.unwrap();
+        }
+        while index_handle.search("", 10, SearchOptions::default()).await.unwrap().len() < 4 {
+            index_handle.commit(String::from("test")).await;
+        }
+
+        let ids = |docs: Vec<SearchDocument>| docs.iter().map(|sdoc| field_to_string(&sdoc.doc, fields.id)).collect::<Vec<String>>();
+        for _ in 0..2 {
+            assert_eq!(ids(index_handle.search("", 2, SearchOptions::default()).await.unwrap()), vec!["z", "a"]);
+            assert_eq!(ids(index_handle.search("", 10, SearchOptions::default()).await.unwrap()), vec!["z", "a", "b", "c"]);
+        }
+
+        let sort = Sort { field: fields.updated_at, order: SortOrder::Asc };
+        let docs = index_handle.search("", 2, SearchOptions { sort: Some(sort), ..SearchOptions::default() }).await.unwrap();
+        assert_eq!(ids(docs), vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn it_should_page_with_the_handle_defaults_unless_asked_otherwise() {
+        init_tracing();
+        let config = IndexerConfig { default_limit: 2, default_offset: 1, ..IndexerConfig::default() };
+        let index_handle = IndexActorHandle::new(RamDirectory::create(), question_index(), config).await.unwrap();
+        let fields = question_fields();
+
+        for (id, updated_at) in [("a", 4000), ("b", 3000), ("c", 2000), ("d", 1000)] {
+            let mut question = new_question("Había una vez un caballo blanco");
+            question.id = String::from(id);
+            let mut doc: Document = new_document(&question, &ServerConfig::default()).into_iter()
+                .filter(|fv| fv.field() != fields.updated_at)
+                .collect::<Vec<FieldValue>>()
+                .into();
+            doc.add_u64(fields.updated_at, updated_at);
+            index_handle.index_single(doc).await.unwrap();
+        }
+        let all = SearchOptions { offset: Some(0), ..SearchOptions::default() };
+        while index_handle.search("", 10, all.clone()).await.unwrap().len() < 4 {
+            index_handle.commit(String::from("test")).await;
+        }
+
+        let ids = |docs: Vec<SearchDocument>| docs.iter().map(|sdoc| field_to_string(&sdoc.doc, fields.id)).collect::<Vec<String>>();
+        assert_eq!(index_handle.limit(None), 2);
+        assert_eq!(index_handle.limit(Some(1000)), MAX_SEARCH_LIMIT);
+        assert_eq!(ids(index_handle.search("", index_handle.limit(None), SearchOptions::default()).await.unwrap()), vec!["b", "c"]);
+        assert_eq!(ids(index_handle.search("", index_handle.limit(Some(3)), all).await.unwrap()), vec!["a", "b", "c"]);
+        let past_the_end = SearchOptions { offset: Some(3), ..SearchOptions::default() };
+        assert_eq!(ids(index_handle.search("", 2, past_the_end).await.unwrap()), vec!["d"]);
+    }
+
+    #[tokio::test]
+    async fn it_should_bust_cached_searches_on_commit() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await.with_query_cache(QueryCache::new(16, Duration::from_secs(60)));
+
+        index_handle.index_single(new_document(&new_question("Había una vez un caballo blanco"), &ServerConfig::default())).await.unwrap();
+        let mut docs = index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap();
+        while docs.is_empty() {
+            index_handle.commit(String::from("test")).await;
+            docs = index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap();
+        }
+        assert_eq!(index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().len(), 1);
+
+        index_handle.index_single(new_document(&new_question("Había una vez un caballo negro"), &ServerConfig::default())).await.unwrap();
+        while docs.len() < 2 {
+            index_handle.commit(String::from("test")).await;
+            docs = index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_match_the_unstemmed_text_exactly() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+        index_handle.index_single(new_document(&new_question("Los caballos blancos del ejército"), &ServerConfig::default())).await.unwrap();
+
+        while index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().is_empty() {
+            index_handle.commit(String::from("test")).await;
+        }
+
+        let exact = || SearchOptions { default_fields: Some(vec![question_fields().question_raw]), ..SearchOptions::default() };
+        assert!(index_handle.search("caballo", 10, exact()).await.unwrap().is_empty());
+        assert_eq!(index_handle.search("caballos", 10, exact()).await.unwrap().len(), 1);
+        assert_eq!(index_handle.search("\"caballos blancos\"", 10, exact()).await.unwrap().len(), 1);
+        assert!(index_handle.search("ejercito", 10, exact()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_only_match_accented_questions_when_accent_sensitive() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+        let accented = new_question("El cañón del río");
+        index_handle.index_single(new_document(&accented, &ServerConfig::default())).await.unwrap();
+        index_handle.index_single(new_document(&new_question("El canon del alquiler"), &ServerConfig::default())).await.unwrap();
+
+        while index_handle.search("canon", 10, SearchOptions::default()).await.unwrap().len() < 2 {
+            index_handle.commit(String::from("test")).await;
+        }
+
+        let fields = question_fields();
+        let accent_sensitive = || SearchOptions { default_fields: Some(vec![fields.question_accented]), ..SearchOptions::default() };
+        assert_eq!(index_handle.search("cañón", 10, SearchOptions::default()).await.unwrap().len(), 2);
+        let docs = index_handle.search("cañón", 10, accent_sensitive()).await.unwrap();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(field_to_string(&docs[0].doc, fields.id), accented.id);
+        assert_eq!(index_handle.search("canon", 10, accent_sensitive()).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_should_only_match_other_word_forms_when_stemming() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+        let singular = new_question("Había una vez un caballo blanco");
+        index_handle.index_single(new_document(&singular, &ServerConfig::default())).await.unwrap();
+        index_handle.index_single(new_document(&new_question("Había una vez dos caballos blancos"), &ServerConfig::default())).await.unwrap();
+
+        while index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().len() < 2 {
+            index_handle.commit(String::from("test")).await;
+        }
+
+        let fields = question_fields();
+        let unstemmed = || SearchOptions { default_fields: Some(vec![fields.question_unstemmed]), ..SearchOptions::default() };
+        let docs = index_handle.search("caballo", 10, unstemmed()).await.unwrap();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(field_to_string(&docs[0].doc, fields.id), singular.id);
+        // Still folding the accents and dropping the stop words
+        assert_eq!(index_handle.search("habia", 10, unstemmed()).await.unwrap().len(), 2);
+        assert!(index_handle.search("una", 10, unstemmed()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_rank_boosted_terms_higher() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+        let horse = new_question("Un caballo y otro caballo");
+        let dog = new_question("Un perro en la sierra");
+        index_handle.index_single(new_document(&horse, &ServerConfig::default())).await.unwrap();
+        index_handle.index_single(new_document(&dog, &ServerConfig::default())).await.unwrap();
+
+        while index_handle.search("caballo perro", 10, SearchOptions::default()).await.unwrap().len() < 2 {
+            index_handle.commit(String::from("test")).await;
+        }
+
+        let id = question_fields().id;
+        let docs = index_handle.search("caballo perro", 10, SearchOptions::default()).await.unwrap();
+        assert_eq!(field_to_string(&docs[0].doc, id), horse.id);
+
+        let docs = index_handle.search("caballo question:perro^3", 10, SearchOptions::default()).await.unwrap();
+        assert_eq!(field_to_string(&docs[0].doc, id), dog.id);
+        assert!(docs[0].score > docs[1].score);
+    }
+
+    #[tokio::test]
+    async fn it_should_drop_the_unparseable_words_of_lenient_searches() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+
+        index_handle.index_single(new_document(&new_question("Había una vez un caballo blanco"), &ServerConfig::default())).await.unwrap();
+        while index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().is_empty() {
+            index_handle.commit(String::from("test")).await;
+        }
+
+        let query = "caballo \"blanco *perro";
+        assert!(matches!(index_handle.search(query, 10, SearchOptions::default()).await, Err(SearchError::Query(_))));
+
+        let lenient = SearchOptions { lenient: true, ..SearchOptions::default() };
+        assert_eq!(index_handle.search(query, 10, lenient.clone()).await.unwrap().len(), 1);
+        let ignored = index_handle.ignored_syntax(query, &lenient);
+        assert_eq!(ignored.len(), 2);
+        assert!(ignored[0].contains("*perro"));
+        assert!(ignored[1].starts_with("\"blanco"));
+        assert!(index_handle.ignored_syntax("caballo", &lenient).is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_require_every_term_with_the_and_operator() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+
+        index_handle.index_single(new_document(&new_question("Había una vez un caballo blanco"), &ServerConfig::default())).await.unwrap();
+        while index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().is_empty() {
+            index_handle.commit(String::from("test")).await;
+        }
+
+        let conjunction = SearchOptions { conjunction: true, ..SearchOptions::default() };
+        assert_eq!(index_handle.search("caballo perro", 10, SearchOptions::default()).await.unwrap().len(), 1);
+        assert!(index_handle.search("caballo perro", 10, conjunction.clone()).await.unwrap().is_empty());
+        assert!(index_handle.search("caball* perro", 10, conjunction.clone()).await.unwrap().is_empty());
+        // The prefix only has to match one of the searched fields
+        let fields = question_fields();
+        let both_fields = SearchOptions { default_fields: Some(vec![fields.question, fields.public_employment_name]), ..conjunction.clone() };
+        assert_eq!(index_handle.search("caball* blanco", 10, both_fields).await.unwrap().len(), 1);
+        assert_eq!(index_handle.search("caballo blanco", 10, conjunction).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_should_group_the_best_hits_by_question_type() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+
+        for (question_type, count) in [("LAW", 3), ("HISTORY", 1)] {
+            for i in 0..count {
+                let mut question = new_question(format!("Había una vez un caballo número {}", i).as_str());
+                question.question_type = String::from(question_type);
+                index_handle.index_single(new_document(&question, &ServerConfig::default())).await.unwrap();
+            }
+        }
+        while index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().len() < 4 {
+            index_handle.commit(String::from("test")).await;
+        }
+
+        let stored_fields = SearchOptions { stored_fields: Some(vec![question_fields().id]), ..SearchOptions::default() };
+        let groups = index_handle.search_grouped("caballo", 200, question_fields().question_type, 2, stored_fields).await.unwrap();
+        let mut sizes: Vec<(&str, usize)> = groups.iter().map(|(question_type, docs)| (question_type.as_str(), docs.len())).collect();
+        sizes.sort();
+        assert_eq!(sizes, vec![("HISTORY", 1), ("LAW", 2)]);
+    }
+
+    #[tokio::test]
+    async fn it_should_serve_the_commits_of_another_writer_when_read_only() {
+        init_tracing();
+        let path = std::env::temp_dir().join(format!("tantivy_search_standby_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        let writer_handle = IndexActorHandle::new(MmapDirectory::open(&path).unwrap(), question_index(), IndexerConfig::default()).await.unwrap();
+
+        let config = IndexerConfig { read_only: true, ..IndexerConfig::default() };
+        let standby_handle = IndexActorHandle::new(MmapDirectory::open(&path).unwrap(), question_index(), config).await.unwrap();
+
+        writer_handle.index_single(new_document(&new_question("Había una vez un caballo blanco"), &ServerConfig::default())).await.unwrap();
+        writer_handle.flush().await.unwrap();
+        let mut found = false;
+        for _ in 0..50 {
+            found = !standby_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().is_empty();
+            if found {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(found);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[tokio::test]
+    async fn it_should_autocomplete_prefixes_with_a_typo() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+
+        for question in ["Había una vez un caballo blanco", "Había una vez un caballo blanco", "Había una vez un cabo"] {
+            index_handle.index_single(new_document(&new_question(question), &ServerConfig::default())).await.unwrap();
+        }
+        while index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().len() < 2 {
+            index_handle.commit(String::from("test")).await;
+        }
+
+        let fields = question_fields();
+        let suggestions = index_handle.autocomplete("un cabalo", fields.question_raw, fields.question, 5).await.unwrap();
+        assert_eq!(suggestions, vec!["Había una vez un caballo blanco"]);
+
+        let suggestions = index_handle.autocomplete("Una vez un cab", fields.question_raw, fields.question, 5).await.unwrap();
+        assert_eq!(suggestions.len(), 2);
+
+        // Too short to tolerate a typo, `cb` would match every word starting with `c`
+        assert!(index_handle.autocomplete("cb", fields.question_raw, fields.question, 5).await.unwrap().is_empty());
+        assert!(index_handle.autocomplete("", fields.question_raw, fields.question, 5).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_keep_an_index_to_erase_while_the_backend_is_unhealthy() {
+        let dir = RamDirectory::create();
+        Index::create(dir.clone(), new_person_schema(), IndexSettings::default()).unwrap();
+
+        let config = IndexerConfig { backend_health_url: Some(String::from("http://127.0.0.1:1/health")), ..IndexerConfig::default() };
+        let result = IndexActorHandle::new(dir.clone(), question_index(), config).await;
+        assert!(matches!(result, Err(SearchError::Reindex(_))));
+        assert_eq!(Index::open(dir.clone()).unwrap().schema(), new_person_schema());
+
+        let index_handle = IndexActorHandle::new(dir.clone(), question_index(), IndexerConfig::default()).await.unwrap();
+        assert!(index_handle.is_reindexing());
+        assert_eq!(Index::open(dir).unwrap().schema(), new_question_schema());
+    }
+
+    #[tokio::test]
+    async fn it_should_boost_the_configured_fields_only() {
+        let boosts = HashMap::from([(String::from("question"), 2.0), (String::from("unknown"), 3.0)]);
+        let config = IndexerConfig { field_boosts: HashMap::from([(String::from("questions"), boosts)]), ..IndexerConfig::default() };
+        let index_handle = IndexActorHandle::new(RamDirectory::create(), question_index(), config).await.unwrap();
+
+        assert!(index_handle.validate("caballo").unwrap().starts_with("Boost(query=TermQuery"));
+        assert!(index_handle.validate("caballo").unwrap().ends_with("boost=2)"));
+        assert!(!new_question_index_handle().await.validate("caballo").unwrap().contains("Boost"));
+    }
+
+    #[tokio::test]
+    async fn it_should_reject_queries_analyzed_into_too_many_terms() {
+        init_tracing();
+        let config = IndexerConfig { max_query_terms: Some(3), ..IndexerConfig::default() };
+        let index_handle = IndexActorHandle::new(RamDirectory::create(), question_index(), config).await.unwrap();
+
+        // Stop words are not terms
+        assert!(index_handle.search("el caballo de la sierra", 10, SearchOptions::default()).await.is_ok());
+        let result = index_handle.search("caballo perro gato burro", 10, SearchOptions::default()).await;
+        assert!(matches!(result, Err(SearchError::Query(_))));
+    }
+
+    #[tokio::test]
+    async fn it_should_order_approximate_searches_by_rank_only() {
+        init_tracing();
+        let index_handle = new_question_index_handle().await;
+        let relevant = new_question("Caballo, caballo y caballo");
+        let mut ranked = new_question("Había una vez un caballo negro en la sierra");
+        ranked.popularity = 1000;
+
+        index_handle.index_single(new_document(&relevant, &ServerConfig::default())).await.unwrap();
+        index_handle.index_single(new_document(&ranked, &ServerConfig::default())).await.unwrap();
+        while index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().len() < 2 {
+            index_handle.commit(String::from("test")).await;
+        }
+
+        let fields = question_fields();
+        let docs = index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap();
+        assert_eq!(field_to_string(&docs[0].doc, fields.id), relevant.id);
+
+        let options = SearchOptions { rank_field: Some(fields.rank), ..SearchOptions::default() };
+        let docs = index_handle.search("caballo", 10, options).await.unwrap();
+        assert_eq!(field_to_string(&docs[0].doc, fields.id), ranked.id);
+        assert_eq!(docs[0].score, 1000.0);
+        assert_eq!(docs[1].score, 0.0);
+    }
 }
\ No newline at end of file
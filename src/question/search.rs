@@ -1,44 +1,499 @@
-use axum::extract::{Query, State};
-use axum::http::StatusCode;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
 use axum::Json;
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use serde::{Deserialize, Serialize};
-use tantivy::Score;
+use serde_json::{json, Map, Value};
+use tantivy::schema::FieldType;
+use tantivy::{Score, Term};
+
+use crate::error::SearchError;
+use crate::indexation::{field_to_facets, field_to_json, field_to_optional_string, field_to_string, field_to_u64};
+use crate::indexation::handle::{SearchDocument, SearchOptions, SnippetOptions, Sort, SortOrder};
+use crate::query_log::log_query;
+use crate::question::{new_question_schema, question_fields, QUESTIONS_INDEX};
+use crate::server::{accepts_ndjson, AppState, ndjson_response, reindexing_response, stats_response, ValidJson};
 
-use crate::indexation::field_to_string;
-use crate::indexation::handle::SearchDocument;
-use crate::question::question_fields;
-use crate::server::AppState;
+/// Characters of each highlighted fragment unless `snippet_len` says otherwise.
+const DEFAULT_SNIPPET_LEN: usize = 150;
+/// Best hits bucketed by `group_by`, the buckets only see these.
+const GROUP_BY_CANDIDATES: usize = 200;
+/// Hits of each `group_by` bucket unless `group_size` says otherwise.
+const DEFAULT_GROUP_SIZE: usize = 3;
+/// Suggestions answered unless `limit` says otherwise, and the most it can ask for.
+const DEFAULT_SUGGESTIONS: usize = 5;
+const MAX_SUGGESTIONS: usize = 20;
+/// Ids an mget can ask for at once.
+const MAX_MGET_IDS: usize = 100;
 
 #[derive(Deserialize)]
 pub struct SearchQuestionQuery {
+    #[serde(default)]
+    query: String,
+    boost_popularity: Option<bool>,
+    /// Decays the relevance of the questions by the age of their `created_at`, halved for every
+    /// RECENCY_HALF_LIFE_SECS. Questions whose `created_at` isn't an RFC 3339 date aren't decayed.
+    recency_boost: Option<bool>,
+    question_type: Option<String>,
+    /// Comma-separated keys to keep in every result, all of them by default.
+    fields: Option<String>,
+    /// Drops the hits scoring less, after the limit is applied.
+    min_score: Option<Score>,
+    /// ISO 639-1 code of the detected question language, like `es`.
+    lang: Option<String>,
+    /// `popularity` or `updated_at`, descending unless suffixed with `:asc`. Defaults to
+    /// `updated_at` when browsing with an empty query, to relevance otherwise.
+    sort: Option<String>,
+    /// Orders by the precomputed question rank instead of relevance, faster but approximate.
+    approximate: Option<bool>,
+    /// `exact` matches the question words as written instead of their stems.
+    #[serde(default, rename = "match")]
+    match_mode: MatchMode,
+    /// Keeps the accents of the query and the questions, so `él` does not match `el`. Ignored
+    /// by `match=exact`, which always keeps them.
+    accent_sensitive: Option<bool>,
+    /// `false` matches the question words as written, still without accents nor stop words,
+    /// instead of their stems, so `caballo` does not match `caballos`. `match=exact` and
+    /// `accent_sensitive` take precedence, use `match=exact` for neither stems nor folding.
+    stemming: Option<bool>,
+    /// `question_type` answers `{"results": [...], "facets": {"question_type": {...}}}` instead
+    /// of the bare results, counting the matching questions of each type. JSON responses only.
+    facet: Option<String>,
+    /// Adds `snippets` to every result, the `question` and `public_employment_name` fragments
+    /// matching the query with their terms wrapped in `<b>` tags.
+    snippets: Option<bool>,
+    /// Length in characters of every snippet fragment.
+    snippet_len: Option<usize>,
+    /// Adds `debug` to every result, the `segment_ord` and `doc_id` it was retrieved from, to
+    /// track down duplicated or stale hits.
+    debug: Option<bool>,
+    /// Drops the words the query syntax can't parse instead of answering 400, listing why in
+    /// the `x-ignored-syntax` header, for search boxes.
+    lenient: Option<bool>,
+    /// `and` requires every unqualified term to match, `or`, the default, any of them.
+    operator: Option<String>,
+    /// `question_type` answers `{"groups": [{"question_type": ..., "results": [...]}]}`, the
+    /// best hits of each type ordered by their best one. Only the best 200 hits are grouped, so a
+    /// type ranking below them is missing or has fewer hits. JSON responses only.
+    group_by: Option<String>,
+    /// Hits of each `group_by` bucket, 3 by default.
+    group_size: Option<usize>,
+    /// Hits answered, the index default one if none, between 1 and 100.
+    limit: Option<usize>,
+    /// Best hits skipped, to page through the results, at most 10000.
+    offset: Option<usize>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    #[default]
+    Stemmed,
+    Exact,
+}
+
+#[derive(Deserialize)]
+pub struct CountQuestionQuery {
+    #[serde(default)]
+    query: String,
+    question_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AutocompleteQuestionQuery {
+    #[serde(default)]
+    prefix: String,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct MgetQuestionRequest {
+    ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ValidateQuestionQuery {
+    #[serde(default)]
+    query: String,
+}
+
+#[derive(Deserialize)]
+pub struct ExplainQuestionQuery {
     query: String,
+    id: String,
+}
+
+#[derive(Deserialize)]
+pub struct FacetQuestionQuery {
+    field: String,
 }
 
 #[derive(Serialize)]
 pub struct SearchQuestionResponse {
     id: String,
-    question: String,
-    public_employment_name: String,
-    question_type: String,
-    created_at: String,
+    // Missing values are left out, an empty one is still answered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    question: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    public_employment_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    question_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created_at: Option<String>,
+    popularity: u64,
+    pub metadata: Value,
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lang: Option<String>,
+    updated_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
     score: Score,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    snippets: BTreeMap<String, String>,
 }
 
+/// Answers a JSON array, or streams one JSON object per line when `application/x-ndjson`
+/// is accepted. Streamed searches are not in the query log, their hits are unknown upfront.
 pub async fn search_questions(State(state): State<AppState>,
-                              search_query: Query<SearchQuestionQuery>) -> impl IntoResponse {
-    let search_result = state.question_index_handle.search(search_query.query.as_str(), 10).await;
+                              headers: HeaderMap,
+                              search_query: Query<SearchQuestionQuery>) -> Response {
+    if state.question_index_handle.is_reindexing() {
+        return reindexing_response(&state);
+    }
+
+    let _permit = match state.search_permit() {
+        Ok(permit) => permit,
+        Err(e) => return e.into_response(),
+    };
+
+    let projection = match response_projection(&search_query.fields) {
+        Ok(p) => p,
+        Err(unknown) => return SearchError::Query(format!("unknown question field {}", unknown)).into_response(),
+    };
+
+    let sort = match search_query.sort.as_deref().map(parse_sort).transpose() {
+        Ok(s) => s,
+        Err(unknown) => return SearchError::Query(format!("unknown question sort {}", unknown)).into_response(),
+    };
+
+    let conjunction = match search_query.operator.as_deref() {
+        None | Some("or") => false,
+        Some("and") => true,
+        Some(unknown) => return SearchError::Query(format!("unknown question query operator {}", unknown)).into_response(),
+    };
+
+    let fields = question_fields();
+    let lenient = search_query.lenient.unwrap_or(false);
+    let limit = state.question_index_handle.limit(search_query.limit);
+    let mut options = SearchOptions { min_score: search_query.min_score, sort, conjunction, lenient, offset: search_query.offset, ..SearchOptions::default() };
+
+    if search_query.boost_popularity.unwrap_or(false) {
+        options.boost_field = Some(fields.popularity);
+    }
+
+    if search_query.recency_boost.unwrap_or(false) {
+        options.recency_field = Some(fields.created_at_millis);
+    }
+
+    if let Some(question_type) = &search_query.question_type {
+        options.filters.push(Term::from_field_text(fields.question_type, question_type));
+    }
+
+    if search_query.approximate.unwrap_or(false) {
+        options.rank_field = Some(fields.rank);
+    }
+
+    // The question text searched by unqualified terms, highlighted in the stored question
+    let mut question_field = fields.question;
+    if let MatchMode::Exact = search_query.match_mode {
+        question_field = fields.question_raw;
+        options.default_fields = Some(vec![fields.question_raw]);
+    } else if search_query.accent_sensitive.unwrap_or(false) {
+        question_field = fields.question_accented;
+        options.default_fields = Some(vec![fields.question_accented]);
+    } else if !search_query.stemming.unwrap_or(true) {
+        question_field = fields.question_unstemmed;
+        options.default_fields = Some(vec![fields.question_unstemmed]);
+    }
+
+    if search_query.snippets.unwrap_or(false) {
+        options.snippets = Some(SnippetOptions {
+            fields: vec![(question_field, fields.question), (fields.public_employment_name, fields.public_employment_name)],
+            max_chars: search_query.snippet_len.unwrap_or(DEFAULT_SNIPPET_LEN),
+        });
+    }
+
+    // Stored lowercased, whether detected or given
+    if let Some(lang) = &search_query.lang {
+        options.filters.push(Term::from_field_text(fields.lang, &lang.to_ascii_lowercase()));
+    }
+
+    if let Some(projection) = &projection {
+        let schema = new_question_schema();
+        options.stored_fields = Some(projection.iter().filter_map(|f| schema.get_field(f)).collect());
+    }
+
+    let debug = search_query.debug.unwrap_or(false);
+    match search_query.group_by.as_deref() {
+        None => {}
+        Some("question_type") => {
+            let group_size = search_query.group_size.unwrap_or(DEFAULT_GROUP_SIZE);
+            if group_size == 0 {
+                return SearchError::Query(String::from("group_size must be greater than 0, got: 0")).into_response();
+            }
+
+            let query = search_query.query.as_str();
+            return match state.question_index_handle.search_grouped(query, GROUP_BY_CANDIDATES, fields.question_type, group_size, options).await {
+                Ok(groups) => {
+                    let groups: Vec<Value> = groups
+                        .iter()
+                        .map(|(question_type, docs)| json!({
+                            "question_type": question_type,
+                            "results": docs.iter().map(|sdoc| question_hit(sdoc, &projection, debug)).collect::<Vec<_>>(),
+                        }))
+                        .collect();
+                    (StatusCode::OK, Json(json!({ "groups": groups }))).into_response()
+                }
+                Err(e) => e.into_response(),
+            };
+        }
+        Some(unknown) => return SearchError::Query(format!("unknown question group_by {}", unknown)).into_response(),
+    }
+
+    if accepts_ndjson(&headers) {
+        return match state.question_index_handle.search_stream(search_query.query.as_str(), limit, options) {
+            Ok(docs) => ndjson_response(docs, move |sdoc| question_hit(sdoc, &projection, debug)),
+            Err(e) => e.into_response(),
+        };
+    }
+
+    let facet_field = match search_query.facet.as_deref() {
+        None => None,
+        Some("question_type") => Some(fields.question_type_facet),
+        Some(unknown) => return SearchError::Query(format!("unknown question facet {}", unknown)).into_response(),
+    };
+
+    let ignored_syntax = state.question_index_handle.ignored_syntax(search_query.query.as_str(), &options);
+    let started = Instant::now();
+    let query = search_query.query.as_str();
+    let (search_result, facet_result) = match facet_field {
+        Some(field) => {
+            let facet_options = options.clone();
+            let (search_result, facet_counts) = tokio::join!(
+                state.question_index_handle.search(query, limit, options),
+                state.question_index_handle.facet_counts(query, &facet_options, field),
+            );
+            (search_result, Some(facet_counts))
+        }
+        None => (state.question_index_handle.search(query, limit, options).await, None),
+    };
+
+    let mut response = match (search_result, facet_result.transpose()) {
+        (Ok(question_docs), Ok(facet_counts)) => {
+            log_query(QUESTIONS_INDEX, &search_query.query, question_docs.len(), started.elapsed());
+            let response: Vec<Map<String, Value>> = question_docs
+                .iter()
+                .map(|sdoc| question_hit(sdoc, &projection, debug))
+                .collect();
+            match facet_counts {
+                Some(counts) => (StatusCode::OK, Json(json!({ "results": response, "facets": { "question_type": counts } }))).into_response(),
+                None => (StatusCode::OK, Json(response)).into_response(),
+            }
+        }
+        (Err(e), _) | (_, Err(e)) => e.into_response(),
+    };
 
-    match search_result {
+    if !ignored_syntax.is_empty() {
+        if let Ok(value) = HeaderValue::from_bytes(ignored_syntax.join("; ").as_bytes()) {
+            response.headers_mut().insert("x-ignored-syntax", value);
+        }
+    }
+
+    response
+}
+
+/// Streams every question as one JSON object per line, for migrations. An empty index answers
+/// an empty body.
+pub async fn export_questions(State(state): State<AppState>) -> Response {
+    if state.question_index_handle.is_reindexing() {
+        return reindexing_response(&state);
+    }
+
+    ndjson_response(state.question_index_handle.export(), document_to_question)
+}
+
+/// Counts the questions matching the query, cheaper than a search as none is retrieved.
+pub async fn count_questions(State(state): State<AppState>,
+                             count_query: Query<CountQuestionQuery>) -> impl IntoResponse {
+    let mut options = SearchOptions::default();
+    if let Some(question_type) = &count_query.question_type {
+        options.filters.push(Term::from_field_text(question_fields().question_type, question_type));
+    }
+
+    match state.question_index_handle.count(count_query.query.as_str(), options).await {
+        Ok(count) => (StatusCode::OK, Json(json!({ "count": count }))).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Suggests the questions completing what was typed so far, tolerating a typo in its last word.
+pub async fn autocomplete_questions(State(state): State<AppState>,
+                                    autocomplete_query: Query<AutocompleteQuestionQuery>) -> impl IntoResponse {
+    let limit = autocomplete_query.limit.unwrap_or(DEFAULT_SUGGESTIONS).min(MAX_SUGGESTIONS);
+    let fields = question_fields();
+
+    match state.question_index_handle.autocomplete(autocomplete_query.prefix.as_str(), fields.question_raw, fields.question, limit).await {
+        Ok(suggestions) => (StatusCode::OK, Json(json!({ "suggestions": suggestions }))).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// The questions of the given ids in the same order, `null` for the ids without one, like the
+/// ones ranked by another service. Their score is always 0.
+pub async fn mget_questions(State(state): State<AppState>, ValidJson(payload): ValidJson<MgetQuestionRequest>) -> Response {
+    if payload.ids.len() > MAX_MGET_IDS {
+        let error = format!("at most {} ids can be fetched at once, got {}", MAX_MGET_IDS, payload.ids.len());
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+    }
+
+    match state.question_index_handle.mget(payload.ids).await {
         Ok(question_docs) => {
-            let response: Vec<SearchQuestionResponse> = question_docs.iter().map(document_to_question).collect();
-            (StatusCode::OK, Json(response))
+            let response: Vec<Option<SearchQuestionResponse>> = question_docs.iter()
+                .map(|sdoc| sdoc.as_ref().map(document_to_question))
+                .collect();
+            (StatusCode::OK, Json(response)).into_response()
         }
-        Err(e) => {
-            tracing::error!("failed to search questions: {:?}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(vec![]))
+        Err(e) => e.into_response(),
+    }
+}
+
+/// 200 when a question has the id, 404 otherwise, without a body.
+pub async fn question_exists(State(state): State<AppState>, Path(question_id): Path<String>) -> StatusCode {
+    match state.question_index_handle.exists(&question_id).await {
+        Ok(true) => StatusCode::OK,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => e.status(),
+    }
+}
+
+/// How many questions are searched and deleted but still stored, and in how many segments.
+pub async fn question_stats(State(state): State<AppState>) -> Response {
+    stats_response(&state.question_index_handle)
+}
+
+/// Checks the query syntax without searching, answering how it was parsed.
+pub async fn validate_question_query(State(state): State<AppState>,
+                                     validate_query: Query<ValidateQuestionQuery>) -> impl IntoResponse {
+    match state.question_index_handle.validate(validate_query.query.as_str()) {
+        Ok(parsed) => (StatusCode::OK, Json(json!({ "query": parsed }))).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Debugs relevance by explaining how the query scores the question with the given id.
+pub async fn explain_question(State(state): State<AppState>,
+                              explain_query: Query<ExplainQuestionQuery>) -> impl IntoResponse {
+    match state.question_index_handle.explain(explain_query.query.as_str(), explain_query.id.as_str()).await {
+        Ok(Some(explanation)) => (StatusCode::OK, Json(json!(explanation))).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(json!({ "error": format!("question {} not found", explain_query.id) }))).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Counts how many questions there are for each value of a facet field over the whole index.
+pub async fn facet_questions(State(state): State<AppState>,
+                             facet_query: Query<FacetQuestionQuery>) -> impl IntoResponse {
+    let schema = new_question_schema();
+    let field = match schema.get_field(facet_query.field.as_str()) {
+        Some(f) if matches!(schema.get_field_entry(f).field_type(), FieldType::Facet(_)) => f,
+        _ => return SearchError::Query(format!("{} is not a question facet field", facet_query.field)).into_response(),
+    };
+
+    match state.question_index_handle.facet_counts("", &SearchOptions::default(), field).await {
+        Ok(counts) => (StatusCode::OK, Json(counts)).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Distinct question types currently indexed with how many questions each one has.
+pub async fn question_types(State(state): State<AppState>) -> impl IntoResponse {
+    match state.question_index_handle.term_counts(question_fields().question_type).await {
+        Ok(counts) => (StatusCode::OK, Json(counts)).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Field names, types and indexing options of the question index.
+pub async fn question_schema(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.question_index_handle.schema().clone())
+}
+
+/// Parses the requested response keys, which must be question schema fields, `score` or
+/// `snippets`.
+/// Returns the first unknown key as error.
+fn response_projection(fields: &Option<String>) -> Result<Option<Vec<String>>, String> {
+    let fields = match fields {
+        Some(f) if !f.trim().is_empty() => f,
+        _ => return Ok(None),
+    };
+
+    let schema = new_question_schema();
+    let mut projection = Vec::new();
+    for field in fields.split(',').map(|f| f.trim()) {
+        if field != "score" && field != "snippets" && schema.get_field(field).is_none() {
+            return Err(field.to_string());
         }
+        projection.push(field.to_string());
     }
+
+    Ok(Some(projection))
+}
+
+/// Parses `field[:asc|:desc]` over the question fast fields, returning the whole value as
+/// error when it is not one.
+fn parse_sort(sort: &str) -> Result<Sort, String> {
+    let fields = question_fields();
+    let (name, order) = match sort.trim().split_once(':') {
+        Some((name, "asc")) => (name, SortOrder::Asc),
+        Some((name, "desc")) => (name, SortOrder::Desc),
+        Some(_) => return Err(sort.to_string()),
+        None => (sort.trim(), SortOrder::Desc),
+    };
+
+    let field = match name {
+        "popularity" => fields.popularity,
+        "updated_at" => fields.updated_at,
+        _ => return Err(sort.to_string()),
+    };
+
+    Ok(Sort { field, order })
+}
+
+/// The projected question, with the address it was retrieved from when debugging.
+fn question_hit(sdoc: &SearchDocument, projection: &Option<Vec<String>>, debug: bool) -> Map<String, Value> {
+    let mut hit = project(document_to_question(sdoc), projection);
+    if let (true, Some(doc_address)) = (debug, sdoc.doc_address) {
+        hit.insert(String::from("debug"), json!({ "segment_ord": doc_address.segment_ord, "doc_id": doc_address.doc_id }));
+    }
+
+    hit
+}
+
+fn project(question: SearchQuestionResponse, projection: &Option<Vec<String>>) -> Map<String, Value> {
+    let mut response = match json!(question) {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    };
+
+    if let Some(projection) = projection {
+        response.retain(|key, _| projection.contains(key));
+    }
+
+    response
 }
 
 pub fn document_to_question(sdoc: &SearchDocument) -> SearchQuestionResponse {
@@ -46,10 +501,17 @@ pub fn document_to_question(sdoc: &SearchDocument) -> SearchQuestionResponse {
 
     SearchQuestionResponse {
         id: field_to_string(&sdoc.doc, fields.id),
-        question: field_to_string(&sdoc.doc, fields.question),
-        public_employment_name: field_to_string(&sdoc.doc, fields.public_employment_name),
-        question_type: field_to_string(&sdoc.doc, fields.question_type),
-        created_at: field_to_string(&sdoc.doc, fields.created_at),
+        question: field_to_optional_string(&sdoc.doc, fields.question),
+        public_employment_name: field_to_optional_string(&sdoc.doc, fields.public_employment_name),
+        question_type: field_to_optional_string(&sdoc.doc, fields.question_type),
+        created_at: field_to_optional_string(&sdoc.doc, fields.created_at),
+        popularity: field_to_u64(&sdoc.doc, fields.popularity),
+        metadata: field_to_json(&sdoc.doc, fields.metadata),
+        tags: field_to_facets(&sdoc.doc, fields.tags),
+        lang: field_to_optional_string(&sdoc.doc, fields.lang),
+        updated_at: field_to_u64(&sdoc.doc, fields.updated_at),
+        expires_at: sdoc.doc.get_first(fields.expires_at).and_then(|v| v.as_u64()),
         score: sdoc.score,
+        snippets: sdoc.snippets.clone(),
     }
 }
\ No newline at end of file
@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use axum::routing::get;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tantivy::Score;
+
+use crate::error::SearchError;
+use crate::indexation::handle::{IndexActorHandle, SearchDocument, SearchOptions};
+use crate::person::search::{document_to_person, SearchPersonResponse};
+use crate::question::search::{document_to_question, SearchQuestionResponse};
+use crate::server::{AppState, reindexing_response};
+
+const DEFAULT_TYPE_LIMIT: usize = 5;
+const MAX_TYPE_LIMIT: usize = 100;
+
+#[derive(Deserialize)]
+pub struct UnifiedSearchQuery {
+    #[serde(default)]
+    query: String,
+    /// Questions returned at most, 5 by default and between 1 and 100.
+    questions_limit: Option<usize>,
+    /// People returned at most, 5 by default and between 1 and 100.
+    people_limit: Option<usize>,
+    /// Multiplies the question scores in `hits`, UNIFIED_QUESTIONS_WEIGHT by default.
+    questions_weight: Option<Score>,
+    /// Multiplies the people scores in `hits`, UNIFIED_PEOPLE_WEIGHT by default.
+    people_weight: Option<Score>,
+}
+
+#[derive(Serialize)]
+struct UnifiedSearchResponse {
+    questions: TypeHits<SearchQuestionResponse>,
+    people: TypeHits<SearchPersonResponse>,
+    /// The hits of every type ranked together by their weighted score.
+    hits: Vec<WeightedHit>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum UnifiedHit {
+    Question(Box<SearchQuestionResponse>),
+    Person(SearchPersonResponse),
+}
+
+#[derive(Serialize)]
+struct WeightedHit {
+    #[serde(flatten)]
+    hit: UnifiedHit,
+    weighted_score: Score,
+}
+
+/// The hits of one index with how many documents match overall, regardless of the limit.
+#[derive(Serialize)]
+struct TypeHits<T> {
+    total: usize,
+    /// Milliseconds the search of these hits took, neither counting them nor serializing them.
+    took_ms: u64,
+    hits: Vec<T>,
+}
+
+pub fn search_routes() -> Router<AppState> {
+    Router::new()
+        .route("/search", get(search_all))
+}
+
+/// Searches the questions and people indexes in parallel, each one up to its own limit, also
+/// ranking the hits of both together by their score times the weight of their type.
+pub async fn search_all(State(state): State<AppState>, search_query: Query<UnifiedSearchQuery>) -> Response {
+    if state.question_index_handle.is_reindexing() || state.person_index_handle.is_reindexing() {
+        return reindexing_response(&state);
+    }
+
+    let _permit = match state.search_permit() {
+        Ok(permit) => permit,
+        Err(e) => return e.into_response(),
+    };
+
+    let defaults = state.config.unified_weights;
+    let questions_weight = search_query.questions_weight.unwrap_or(defaults.questions);
+    let people_weight = search_query.people_weight.unwrap_or(defaults.people);
+    if questions_weight < 0.0 || people_weight < 0.0 {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": "weights must be non-negative numbers" }))).into_response();
+    }
+
+    let query = search_query.query.as_str();
+    let (questions, people) = tokio::join!(
+        search_type(&state.question_index_handle, query, type_limit(search_query.questions_limit)),
+        search_type(&state.person_index_handle, query, type_limit(search_query.people_limit)),
+    );
+
+    match (questions, people) {
+        (Ok((questions_total, questions, questions_took)), Ok((people_total, people, people_took))) => {
+            let mut hits: Vec<WeightedHit> = questions.iter()
+                .map(|sdoc| WeightedHit { hit: UnifiedHit::Question(Box::new(document_to_question(sdoc))), weighted_score: sdoc.score * questions_weight })
+                .chain(people.iter().map(|sdoc| WeightedHit { hit: UnifiedHit::Person(document_to_person(sdoc)), weighted_score: sdoc.score * people_weight }))
+                .collect();
+            // Stable, so questions still come first on ties
+            hits.sort_by(|a, b| b.weighted_score.total_cmp(&a.weighted_score));
+
+            let response = UnifiedSearchResponse {
+                questions: TypeHits { total: questions_total, took_ms: questions_took.as_millis() as u64, hits: questions.iter().map(document_to_question).collect() },
+                people: TypeHits { total: people_total, took_ms: people_took.as_millis() as u64, hits: people.iter().map(document_to_person).collect() },
+                hits,
+            };
+            (StatusCode::OK, Json(response)).into_response()
+        }
+        (Err(e), _) | (_, Err(e)) => e.into_response(),
+    }
+}
+
+fn type_limit(limit: Option<usize>) -> usize {
+    limit.unwrap_or(DEFAULT_TYPE_LIMIT).clamp(1, MAX_TYPE_LIMIT)
+}
+
+async fn search_type(handle: &IndexActorHandle, query: &str, limit: usize) -> Result<(usize, Vec<SearchDocument>, Duration), SearchError> {
+    let (total, search) = tokio::join!(
+        handle.count(query, SearchOptions::default()),
+        // Counted from the first hit like the total, whatever DEFAULT_SEARCH_OFFSET says
+        handle.timed_search(query, limit, SearchOptions { offset: Some(0), ..SearchOptions::default() }),
+    );
+    let (docs, took) = search?;
+
+    Ok((total?, docs, took))
+}
\ No newline at end of file
@@ -0,0 +1,90 @@
+use axum::{async_trait, Json};
+use axum::body::{Bytes, HttpBody};
+use axum::extract::FromRequest;
+use axum::http::{header, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::BoxError;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+/// Like `Json` but rejecting malformed bodies with a 400 `{"error": "invalid JSON: ..."}`,
+/// including the path of the offending `field` when serde can tell it, instead of axum's plain
+/// text 422.
+pub struct ValidJson<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for ValidJson<T>
+    where T: DeserializeOwned,
+          S: Send + Sync,
+          B: HttpBody + Send + 'static,
+          B::Data: Send,
+          B::Error: Into<BoxError> {
+    type Rejection = Response;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        if !is_json(&req) {
+            let body = json!({ "error": "expected a request with the content type application/json" });
+            return Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, Json(body)).into_response());
+        }
+
+        // Keeps the 413 of the body limit
+        let bytes = Bytes::from_request(req, state).await.map_err(IntoResponse::into_response)?;
+        let deserializer = &mut serde_json::Deserializer::from_slice(&bytes);
+        match serde_path_to_error::deserialize(deserializer) {
+            Ok(value) => Ok(ValidJson(value)),
+            Err(err) => Err(invalid_json_response(err)),
+        }
+    }
+}
+
+fn is_json<B>(req: &Request<B>) -> bool {
+    req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|mime| {
+            let mime = mime.trim().to_ascii_lowercase();
+            mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+        })
+        .unwrap_or(false)
+}
+
+fn invalid_json_response(err: serde_path_to_error::Error<serde_json::Error>) -> Response {
+    let path = err.path().to_string();
+    let inner = err.into_inner();
+    let detail = inner.to_string();
+    let field = if inner.is_data() { error_field(&path, &detail) } else { None };
+
+    let body = match field {
+        Some(field) => json!({ "error": format!("invalid JSON: {}", detail), "field": field }),
+        None => json!({ "error": format!("invalid JSON: {}", detail) }),
+    };
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
+/// serde reports a missing field at the path of the object lacking it, `.` for the root one, so
+/// its name is taken from the message.
+fn error_field(path: &str, detail: &str) -> Option<String> {
+    let parent = if path == "." { None } else { Some(path) };
+    let missing = detail.strip_prefix("missing field `")
+        .and_then(|rest| rest.split('`').next());
+
+    match (parent, missing) {
+        (Some(parent), Some(missing)) => Some(format!("{}.{}", parent, missing)),
+        (None, Some(missing)) => Some(missing.to_string()),
+        (parent, None) => parent.map(|parent| parent.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::json::error_field;
+
+    #[test]
+    fn it_should_point_at_the_offending_field() {
+        assert_eq!(error_field("popularity", "invalid type: string \"a\", expected u64 at line 1 column 20"), Some(String::from("popularity")));
+        assert_eq!(error_field(".", "missing field `title` at line 1 column 2"), Some(String::from("title")));
+        assert_eq!(error_field("questions[1]", "missing field `id` at line 1 column 30"), Some(String::from("questions[1].id")));
+        assert_eq!(error_field(".", "invalid type: integer `1`, expected a map at line 1 column 1"), None);
+    }
+}
\ No newline at end of file
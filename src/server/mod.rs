@@ -1,20 +1,47 @@
-use std::fs;
-
-use axum::{
-    Router, routing::delete, routing::get, routing::post,
-};
-use tantivy::directory::MmapDirectory;
-use tantivy::schema::Schema;
-use tantivy::TantivyError;
-
-use crate::AppEnv;
-use crate::indexation::handle::IndexActorHandle;
-use crate::person::indexation::{delete_person, index_person, reindex_person};
-use crate::person::new_person_schema;
-use crate::person::search::search_people;
-use crate::question::indexation::{delete_question, index_question, reindex_question};
-use crate::question::new_question_schema;
-use crate::question::search::search_questions;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::path::Path;
+use std::{fs, io};
+
+use axum::{Json, Router};
+use axum::body::{Body, StreamBody};
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::middleware::{self, Next};
+use axum::routing::get;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tantivy::Directory;
+use tantivy::directory::{MmapDirectory, RamDirectory};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::ReceiverStream;
+use tower::ServiceBuilder;
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::TraceLayer;
+use tracing::Span;
+
+use crate::analyze::analyze_routes;
+use crate::config::{Config, DirectoryKind, ServerConfig};
+use crate::error::SearchError;
+use crate::indexation::handle::{IndexActorHandle, SearchDocument};
+use crate::indexation::IndexDefinition;
+use crate::organization::{organization_index, organization_routes, ORGANIZATIONS_INDEX};
+use crate::person::{PEOPLE_INDEX, person_index, person_routes};
+use crate::question::{question_index, question_routes, QUESTIONS_INDEX};
+use crate::search::search_routes;
+
+mod json;
+
+pub use json::ValidJson;
+
+const X_REQUEST_ID: &str = "x-request-id";
+const NDJSON: &str = "application/x-ndjson";
+/// POST routes only reading the indexes, public like the GET ones.
+const READ_POSTS: [&str; 1] = ["/questions/mget"];
 
 /// Only one index writer and one reader is allowed for the entire lifetime of the server.
 /// For each, we spawn a regular OS thread with std::thread::spawn.
@@ -23,34 +50,1147 @@ use crate::question::search::search_questions;
 pub struct AppState {
     pub question_index_handle: IndexActorHandle,
     pub person_index_handle: IndexActorHandle,
-    pub backend_env: AppEnv,
+    pub organization_index_handle: IndexActorHandle,
+    pub config: Arc<ServerConfig>,
+    /// Bounds the searches in flight, so they fail fast instead of piling up blocking tasks.
+    search_permits: Option<Arc<Semaphore>>,
+}
+
+impl AppState {
+    fn new(mut handles: HashMap<&str, IndexActorHandle>, config: ServerConfig) -> Self {
+        let mut take = |name: &str| handles.remove(name).unwrap_or_else(|| panic!("no index defined for {}", name));
+
+        AppState {
+            question_index_handle: take(QUESTIONS_INDEX),
+            person_index_handle: take(PEOPLE_INDEX),
+            organization_index_handle: take(ORGANIZATIONS_INDEX),
+            search_permits: config.max_concurrent_searches.map(|permits| Arc::new(Semaphore::new(permits))),
+            config: Arc::new(config),
+        }
+    }
+
+    /// Held by a search until it answers, `Overloaded` when every permit is taken.
+    pub fn search_permit(&self) -> Result<Option<OwnedSemaphorePermit>, SearchError> {
+        match &self.search_permits {
+            Some(permits) => permits.clone().try_acquire_owned().map(Some).map_err(|_| SearchError::Overloaded),
+            None => Ok(None),
+        }
+    }
+
+    fn handles(&self) -> [&IndexActorHandle; 3] {
+        [&self.question_index_handle, &self.person_index_handle, &self.organization_index_handle]
+    }
+
+    /// Commits the pending changes of every index, waiting for them.
+    pub async fn flush(&self) {
+        for handle in self.handles() {
+            match handle.flush().await {
+                Ok(opstamp) => tracing::info!("{} index flushed with opstamp {}", handle.name(), opstamp),
+                Err(e) => tracing::error!("failed to flush {} index: {:?}", handle.name(), e),
+            }
+        }
+    }
+}
+
+/// Every index served. Adding an entity means adding its definition here, its handle to
+/// `AppState` and merging its routes.
+fn index_definitions() -> Vec<IndexDefinition> {
+    vec![question_index(), person_index(), organization_index()]
 }
 
-pub async fn new_router(backend_env: AppEnv) -> Result<Router, TantivyError> {
+pub async fn new_state(config: &Config) -> Result<AppState, SearchError> {
     // Init indexers
-    let question_index_handle = new_index_actor("idx_questions", new_question_schema(), String::from("questions"), backend_env.clone()).await?;
-    let person_index_handle = new_index_actor("idx_people", new_person_schema(), String::from("people"), backend_env.clone()).await?;
-
-    // Init app state
-    let app_state = AppState {
-        question_index_handle,
-        person_index_handle,
-        backend_env,
+    let definitions = index_definitions();
+    for index_name in config.indexer.field_boosts.keys() {
+        if !definitions.iter().any(|definition| definition.name == index_name) {
+            tracing::warn!("ignoring the boosts of {}, no such index", index_name);
+        }
+    }
+
+    let mut handles = HashMap::new();
+    for definition in definitions {
+        let name = definition.name;
+        let dir = new_directory(config.directory, &config.index_dir, definition.directory);
+        handles.insert(name, IndexActorHandle::new(dir, definition, config.indexer.clone()).await?);
+    }
+
+    Ok(AppState::new(handles, config.server.clone()))
+}
+
+pub fn routes(app_state: AppState) -> Router {
+    let x_request_id = HeaderName::from_static(X_REQUEST_ID);
+    let config = app_state.config.clone();
+
+    Router::new()
+        .merge(question_routes())
+        .merge(person_routes())
+        .merge(organization_routes())
+        .merge(search_routes())
+        .merge(analyze_routes())
+        .route("/admin/indexers", get(indexers))
+        .layer(middleware::from_fn_with_state(config.index_api_key.clone(), require_api_key))
+        // Bounds the JSON payloads of the indexing endpoints, bigger ones get a 413
+        .layer(DefaultBodyLimit::max(config.max_body_bytes))
+        .layer(
+            // Reuses the X-Request-Id sent by the Go backend, or generates one, so every log line
+            // of a request is correlated by its span, and echoes it back in the response.
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::new(x_request_id.clone(), MakeRequestUuid))
+                .layer(TraceLayer::new_for_http().make_span_with(request_span))
+                .layer(PropagateRequestIdLayer::new(x_request_id))
+                .layer(cors_layer(&config.cors_allowed_origins))
+                // Empty bodies, like the 202 of the indexing endpoints, are never compressed
+                .layer(CompressionLayer::new())
+        )
+        .with_state(app_state)
+}
+
+/// Indexing, deleting and every other route changing an index needs an `Authorization:
+/// Bearer <INDEX_API_KEY>` header, answering a 401 otherwise. Reads stay public, and every
+/// request is allowed when INDEX_API_KEY is unset, like in development.
+async fn require_api_key<B>(State(api_key): State<Option<Arc<str>>>, request: Request<B>, next: Next<B>) -> Response {
+    let is_read = matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+        || (request.method() == Method::POST && READ_POSTS.contains(&request.uri().path()));
+    let authorized = match &api_key {
+        Some(api_key) if !is_read => request.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|key| constant_time_eq(key.as_bytes(), api_key.as_bytes()))
+            .unwrap_or(false),
+        _ => true,
+    };
+
+    if authorized {
+        next.run(request).await
+    } else {
+        StatusCode::UNAUTHORIZED.into_response()
+    }
+}
+
+/// Compares every byte no matter where the first difference is, so the response time doesn't
+/// tell how much of a guessed key is right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Origins allowed to call the API from a browser, taken from CORS_ALLOWED_ORIGINS as a
+/// comma-separated list, or `*` for any origin. No origin is allowed when unset.
+fn cors_layer(allowed_origins: &str) -> CorsLayer {
+    let allow_origin = if allowed_origins.trim() == "*" {
+        AllowOrigin::from(Any)
+    } else {
+        let origins: Vec<HeaderValue> = allowed_origins
+            .split(',')
+            .map(|o| o.trim())
+            .filter(|o| !o.is_empty())
+            .filter_map(|o| match HeaderValue::from_str(o) {
+                Ok(origin) => Some(origin),
+                Err(_) => {
+                    tracing::warn!("ignoring invalid CORS origin: {}", o);
+                    None
+                }
+            })
+            .collect();
+        AllowOrigin::list(origins)
     };
 
-    Ok(Router::new()
-        .route("/questions", get(search_questions).post(index_question))
-        .route("/questions/reindex", post(reindex_question))
-        .route("/questions/:question_id", delete(delete_question))
-        .route("/people", get(search_people).post(index_person))
-        .route("/people/reindex", post(reindex_person))
-        .route("/people/:person_id", delete(delete_person))
-        .with_state(app_state))
-}
-
-async fn new_index_actor(path: &str, schema: Schema, index_name: String, backend_env: AppEnv) -> Result<IndexActorHandle, TantivyError> {
-    let path = format!("{}{}", "index/", path);
-    fs::create_dir_all(path.as_str()).unwrap();
-    let dir = MmapDirectory::open(path).unwrap();
-    IndexActorHandle::new(dir, schema, index_name, backend_env).await
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+}
+
+fn request_span(request: &Request<Body>) -> Span {
+    let request_id = request.headers()
+        .get(X_REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    tracing::info_span!("request", request_id, method = %request.method(), uri = %request.uri())
+}
+
+/// Queue free slots and liveness of every index actor, a queue running out of free slots means
+/// indexing requests are about to wait for the actor.
+async fn indexers(State(state): State<AppState>) -> impl IntoResponse {
+    let indexers: Vec<_> = state.handles()
+        .iter()
+        .map(|handle| json!({ "name": handle.name(), "queue_free": handle.queue_free(), "alive": handle.is_alive() }))
+        .collect();
+
+    Json(indexers)
+}
+
+/// Answered by searches while their index is being rebuilt after a schema change, telling
+/// clients to come back after REINDEX_RETRY_AFTER_SECS seconds.
+pub fn reindexing_response(state: &AppState) -> Response {
+    let retry_after = state.config.reindex_retry_after_secs;
+
+    (StatusCode::SERVICE_UNAVAILABLE, [(header::RETRY_AFTER, retry_after.to_string())], Json(json!({ "error": "index is being rebuilt" }))).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct CommitQuery {
+    /// Waits for the changes to be committed, answering a 200 with the commit opstamp instead
+    /// of a 202 once they're queued.
+    commit: Option<bool>,
+}
+
+impl CommitQuery {
+    pub fn wait_for_commit(&self) -> bool {
+        self.commit.unwrap_or(false)
+    }
+}
+
+/// Answered once every change queued before is committed, and searchable after the reader
+/// reloads on that commit.
+pub async fn committed_response(handle: &IndexActorHandle) -> Response {
+    match handle.flush().await {
+        Ok(opstamp) => (StatusCode::OK, Json(json!({ "opstamp": opstamp }))).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Answers the stats of an index, the same ones for every entity.
+pub fn stats_response(handle: &IndexActorHandle) -> Response {
+    match handle.stats() {
+        Ok(stats) => (StatusCode::OK, Json(json!({
+            "num_docs": stats.num_docs,
+            "deleted_docs": stats.deleted_docs,
+            "segments": stats.segments,
+            "opstamp": stats.opstamp,
+            "reindexing": handle.is_reindexing(),
+        }))).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Whether the client asked for newline-delimited JSON instead of a JSON array.
+pub fn accepts_ndjson(headers: &HeaderMap) -> bool {
+    headers.get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.contains(NDJSON))
+}
+
+/// Streams every document as one JSON object per line while it's being retrieved. Errors
+/// can't change the status anymore, so they cut the response short.
+pub fn ndjson_response<T, F>(docs: mpsc::Receiver<Result<SearchDocument, SearchError>>, to_line: F) -> Response
+    where T: Serialize, F: Fn(&SearchDocument) -> T + Send + 'static {
+    let lines = ReceiverStream::new(docs).map(move |result| {
+        result
+            .and_then(|sdoc| {
+                let mut line = serde_json::to_vec(&to_line(&sdoc)).map_err(io::Error::from)?;
+                line.push(b'\n');
+                Ok(line)
+            })
+            .map_err(|e| {
+                tracing::error!("failed to stream search results: {:?}", e);
+                e
+            })
+    });
+
+    ([(header::CONTENT_TYPE, NDJSON)], StreamBody::new(lines)).into_response()
+}
+
+/// Some network filesystems can't be memory mapped, then the index is kept in memory instead
+/// of refusing to start, which is loudly warned as it's lost on restart.
+fn new_directory(kind: DirectoryKind, index_dir: &Path, directory: &str) -> Box<dyn Directory> {
+    match kind {
+        DirectoryKind::Ram => Box::new(RamDirectory::create()),
+        DirectoryKind::Mmap => {
+            let path = index_dir.join(directory);
+            fs::create_dir_all(&path).unwrap();
+
+            match MmapDirectory::open(&path) {
+                Ok(dir) => Box::new(dir),
+                Err(e) => {
+                    tracing::warn!("failed to mmap {}, falling back to an in-memory index that is lost on restart: {:?}", path.display(), e);
+                    Box::new(RamDirectory::create())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use axum::{middleware, Router};
+    use axum::routing::get;
+    use tantivy::{Index, IndexSettings};
+    use tantivy::directory::RamDirectory;
+    use tower::ServiceExt;
+
+    use crate::config::{DEFAULT_MAX_BODY_BYTES, DirectoryKind, IndexerConfig, ServerConfig};
+    use crate::indexation::handle::{IndexActorHandle, SearchOptions};
+    use crate::person::person_index;
+    use crate::question::{new_question_schema, QUESTIONS_INDEX};
+    use crate::server::{AppState, index_definitions, new_directory, require_api_key, routes};
+
+    async fn new_test_state() -> AppState {
+        new_test_state_with(ServerConfig::default()).await
+    }
+
+    async fn new_test_state_with(config: ServerConfig) -> AppState {
+        let mut handles = HashMap::new();
+        for definition in index_definitions() {
+            let name = definition.name;
+            let dir = new_directory(DirectoryKind::Ram, Path::new("index"), definition.directory);
+            handles.insert(name, IndexActorHandle::new(dir, definition, IndexerConfig::default()).await.unwrap());
+        }
+
+        AppState::new(handles, config)
+    }
+
+    const QUESTION: &str = r#"{"id":"1","question":"Había una vez un caballo blanco","public_employment_name":"Public Employment","question_type":"ADMINISTRATION","created_at":"asd"}"#;
+
+    fn json_request(uri: &str, body: &str) -> Request<Body> {
+        Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn send(router: &Router, request: Request<Body>) -> axum::response::Response {
+        router.clone().oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_should_compress_search_responses_but_not_empty_accepted_ones() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        let mut index_request = json_request("/questions", QUESTION);
+        index_request.headers_mut().insert(header::ACCEPT_ENCODING, "gzip".parse().unwrap());
+        let response = send(&router, index_request).await;
+
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+
+        while state.question_index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().is_empty() {
+            state.question_index_handle.commit(String::from("questions")).await;
+        }
+
+        let search_request = Request::get("/questions?query=caballo")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&router, search_request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+    }
+
+    #[tokio::test]
+    async fn it_should_reject_too_large_bodies() {
+        let router = routes(new_test_state().await);
+
+        let reindex_request = Request::post("/questions/reindex")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(vec![b' '; DEFAULT_MAX_BODY_BYTES + 1]))
+            .unwrap();
+        let response = send(&router, reindex_request).await;
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn it_should_reject_searches_beyond_the_concurrency_limit() {
+        let state = new_test_state_with(ServerConfig { max_concurrent_searches: Some(2), ..ServerConfig::default() }).await;
+        let router = routes(state.clone());
+
+        // Two searches still in flight
+        let in_flight = [state.search_permit().unwrap(), state.search_permit().unwrap()];
+        for uri in ["/questions?query=caballo", "/people?query=ana", "/organizations?query=ayuntamiento", "/search?query=caballo"] {
+            let response = send(&router, Request::get(uri).body(Body::empty()).unwrap()).await;
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE, "{}", uri);
+        }
+
+        drop(in_flight);
+        let response = send(&router, Request::get("/questions?query=caballo").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn it_should_get_questions_by_id_in_the_requested_order() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        for question in [QUESTION.to_string(), QUESTION.replace(r#""id":"1""#, r#""id":"2""#)] {
+            let response = send(&router, json_request("/questions", &question)).await;
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+        }
+        while state.question_index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().len() < 2 {
+            state.question_index_handle.commit(String::from("questions")).await;
+        }
+
+        let response = send(&router, json_request("/questions/mget", r#"{"ids":["2","missing","1"]}"#)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let questions: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(questions.len(), 3);
+        assert_eq!(questions[0]["id"], "2");
+        assert!(questions[1].is_null());
+        assert_eq!(questions[2]["id"], "1");
+
+        let too_many = serde_json::json!({ "ids": vec!["1"; 101] }).to_string();
+        let response = send(&router, json_request("/questions/mget", &too_many)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn it_should_reject_malformed_json_pointing_at_the_field() {
+        let router = routes(new_test_state().await);
+
+        let wrong_type = QUESTION.replace(r#""created_at":"asd""#, r#""created_at":"asd","popularity":"high""#);
+        for (body, field) in [(wrong_type.as_str(), Some("popularity")), (r#"{"id":"1"}"#, Some("question")), (r#"{"id":"1","#, None)] {
+            let response = send(&router, json_request("/questions", body)).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", body);
+
+            let body_bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let error: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+            assert!(error["error"].as_str().unwrap().starts_with("invalid JSON: "), "{}", error);
+            assert_eq!(error["field"].as_str(), field, "{}", error);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_reject_questions_longer_than_the_configured_length() {
+        let router = routes(new_test_state_with(ServerConfig { max_question_len: Some(10), ..ServerConfig::default() }).await);
+
+        let batch = format!(r#"{{"questions":[{}]}}"#, QUESTION);
+        for request in [json_request("/questions", QUESTION), json_request("/questions/reindex", &batch)] {
+            let response = send(&router, request).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error, serde_json::json!({"error": "question is longer than 10 characters"}));
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_explain_the_rejected_languages() {
+        let router = routes(new_test_state().await);
+
+        let question = QUESTION.replace(r#""created_at":"asd""#, r#""created_at":"asd","lang":"spanish""#);
+        let batch = format!(r#"{{"questions":[{}]}}"#, question);
+        let patch = Request::patch("/questions/1")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"lang":"spanish"}"#))
+            .unwrap();
+        for request in [json_request("/questions", &question), json_request("/questions/reindex", &batch), patch] {
+            let response = send(&router, request).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error, serde_json::json!({"error": "lang must be an ISO 639-1 code, got: spanish"}));
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_answer_at_least_one_hit_and_reject_huge_offsets() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        send(&router, json_request("/questions", QUESTION)).await;
+        send(&router, json_request("/people", r#"{"id":"1","email":"ana@empleos.es"}"#)).await;
+        while state.question_index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().is_empty() {
+            state.question_index_handle.commit(String::from("questions")).await;
+        }
+        while state.person_index_handle.search("", 10, SearchOptions::default()).await.unwrap().is_empty() {
+            state.person_index_handle.commit(String::from("people")).await;
+        }
+
+        for uri in ["/questions?query=caballo&limit=0", "/people?query=ana&limit=0"] {
+            let response = send(&router, Request::get(uri).body(Body::empty()).unwrap()).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let hits: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+            assert_eq!(hits.len(), 1, "{}", uri);
+        }
+
+        for offset in ["10001", "10000000000", "18446744073709551615"] {
+            for path in ["/questions", "/people", "/organizations"] {
+                let uri = format!("{}?query=caballo&offset={}", path, offset);
+                let response = send(&router, Request::get(uri.as_str()).body(Body::empty()).unwrap()).await;
+                assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_return_all_documents_for_an_empty_query() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        send(&router, json_request("/questions", QUESTION)).await;
+
+        while state.question_index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().is_empty() {
+            state.question_index_handle.commit(String::from("questions")).await;
+        }
+
+        let response = send(&router, Request::get("/questions?query=").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let questions: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(questions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_should_only_return_the_requested_question_fields() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        send(&router, json_request("/questions", QUESTION)).await;
+
+        while state.question_index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().is_empty() {
+            state.question_index_handle.commit(String::from("questions")).await;
+        }
+
+        let response = send(&router, Request::get("/questions?query=caballo&fields=id,question").body(Body::empty()).unwrap()).await;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let questions: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(questions, vec![serde_json::json!({"id": "1", "question": "Había una vez un caballo blanco"})]);
+
+        let response = send(&router, Request::get("/questions?query=caballo&fields=id,unknown").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn it_should_explain_the_rejected_search_params() {
+        let router = routes(new_test_state().await);
+
+        let rejected = [
+            ("/questions?query=caballo&fields=id,unknown", "invalid query: unknown question field unknown"),
+            ("/questions?query=caballo&sort=unknown", "invalid query: unknown question sort unknown"),
+            ("/questions?query=caballo&operator=xor", "invalid query: unknown question query operator xor"),
+            ("/questions/facets?field=question_type", "invalid query: question_type is not a question facet field"),
+            ("/questions?query=caballo&group_by=question_type&group_size=0", "invalid query: group_size must be greater than 0, got: 0"),
+            ("/questions?query=caballo&group_by=unknown", "invalid query: unknown question group_by unknown"),
+            ("/questions?query=caballo&facet=unknown", "invalid query: unknown question facet unknown"),
+        ];
+        for (uri, message) in rejected {
+            let response = send(&router, Request::get(uri).body(Body::empty()).unwrap()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error, serde_json::json!({"error": message}), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_only_return_the_doc_addresses_when_debugging() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        send(&router, json_request("/questions", QUESTION)).await;
+
+        while state.question_index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().is_empty() {
+            state.question_index_handle.commit(String::from("questions")).await;
+        }
+
+        let response = send(&router, Request::get("/questions?query=caballo&fields=id&debug=true").body(Body::empty()).unwrap()).await;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let questions: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(questions, vec![serde_json::json!({"id": "1", "debug": {"segment_ord": 0, "doc_id": 0}})]);
+
+        let response = send(&router, Request::get("/questions?query=caballo&fields=id").body(Body::empty()).unwrap()).await;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let questions: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(questions, vec![serde_json::json!({"id": "1"})]);
+    }
+
+    #[tokio::test]
+    async fn it_should_generate_the_id_of_questions_indexed_without_one() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        let response = send(&router, json_request("/questions", r#"{"question":"Había una vez un caballo blanco","public_employment_name":"Public Employment","question_type":"ADMINISTRATION","created_at":"asd"}"#)).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let id = serde_json::from_slice::<serde_json::Value>(&body).unwrap()["id"].as_str().unwrap().to_string();
+        assert!(uuid::Uuid::parse_str(&id).is_ok());
+
+        let query = format!("id:{}", id);
+        while state.question_index_handle.search(&query, 10, SearchOptions::default()).await.unwrap().is_empty() {
+            state.question_index_handle.commit(String::from("questions")).await;
+        }
+
+        let response = send(&router, Request::delete(format!("/questions/{}", id)).body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        while !state.question_index_handle.search(&query, 10, SearchOptions::default()).await.unwrap().is_empty() {
+            state.question_index_handle.commit(String::from("questions")).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_answer_an_indexed_question_once_committed() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        let response = send(&router, json_request("/questions?commit=true", QUESTION)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let committed: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(committed["id"], "1");
+
+        assert_eq!(state.question_index_handle.reload().unwrap().opstamp, committed["opstamp"].as_u64().unwrap());
+        assert_eq!(state.question_index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_should_answer_the_normalized_id_of_an_indexed_question() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+        let question = QUESTION.replace(r#""id":"1""#, r#""id":"ABC""#);
+
+        for (uri, status) in [("/questions", StatusCode::ACCEPTED), ("/questions?commit=true", StatusCode::OK)] {
+            let response = send(&router, json_request(uri, &question)).await;
+            assert_eq!(response.status(), status, "{}", uri);
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let indexed: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(indexed["id"], "abc", "{}", uri);
+        }
+
+        state.question_index_handle.reload().unwrap();
+        let response = send(&router, Request::get("/questions?query=caballo&fields=id").body(Body::empty()).unwrap()).await;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let questions: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(questions, vec![serde_json::json!({"id": "abc"})]);
+    }
+
+    #[tokio::test]
+    async fn it_should_answer_reindexed_batches_once_committed() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+        let batch = format!(r#"{{"questions":[{}, {}]}}"#, QUESTION, QUESTION.replace(r#""id":"1""#, r#""id":"2""#));
+
+        let response = send(&router, json_request("/questions/reindex?commit=true", &batch)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let opstamp = serde_json::from_slice::<serde_json::Value>(&body).unwrap()["opstamp"].as_u64().unwrap();
+        assert!(opstamp > 0);
+
+        let reader = state.question_index_handle.reload().unwrap();
+        assert_eq!(reader.opstamp, opstamp);
+        assert_eq!(state.question_index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().len(), 2);
+
+        assert_eq!(send(&router, json_request("/questions/reindex", &batch)).await.status(), StatusCode::ACCEPTED);
+    }
+
+    #[tokio::test]
+    async fn it_should_index_and_search_organizations_on_their_own_index() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        let organization = r#"{"id":"1","name":"Ayuntamiento de Madrid","sector":"PUBLIC","location":"Madrid"}"#;
+        let response = send(&router, json_request("/organizations", organization)).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        while state.organization_index_handle.search("ayuntamiento", 10, SearchOptions::default()).await.unwrap().is_empty() {
+            state.organization_index_handle.commit(String::from("organizations")).await;
+        }
+
+        let response = send(&router, Request::get("/organizations?query=madrid").body(Body::empty()).unwrap()).await;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let organizations: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(organizations.len(), 1);
+        assert_eq!(organizations[0]["sector"], "PUBLIC");
+
+        assert!(state.question_index_handle.search("", 10, SearchOptions::default()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_describe_the_index_schemas() {
+        let router = routes(new_test_state().await);
+
+        let response = send(&router, Request::get("/questions/schema").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let fields: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        let id = fields.iter().find(|f| f["name"] == "id").unwrap();
+        assert_eq!(id["type"], "text");
+        assert_eq!(id["options"]["stored"], true);
+
+        let response = send(&router, Request::get("/people/schema").body(Body::empty()).unwrap()).await;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let fields: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert!(fields.iter().any(|f| f["name"] == "email"));
+    }
+
+    #[tokio::test]
+    async fn it_should_find_people_by_email_domain() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        for person in [r#"{"id":"1","email":"ana@Empleos.es"}"#, r#"{"id":"2","email":"luis@gmail.com"}"#, r#"{"id":"3","email":"no-domain"}"#] {
+            assert_eq!(send(&router, json_request("/people", person)).await.status(), StatusCode::ACCEPTED);
+        }
+
+        while state.person_index_handle.search("", 10, SearchOptions::default()).await.unwrap().len() < 3 {
+            state.person_index_handle.commit(String::from("people")).await;
+        }
+
+        let response = send(&router, Request::get("/people?domain=empleos.es").body(Body::empty()).unwrap()).await;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let people: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(people.len(), 1);
+        assert_eq!(people[0]["email"], "ana@Empleos.es");
+    }
+
+    #[tokio::test]
+    async fn it_should_count_and_describe_people_like_questions() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        for person in [r#"{"id":"1","email":"ana@empleos.es"}"#, r#"{"id":"2","email":"luis@empleos.es"}"#] {
+            send(&router, json_request("/people", person)).await;
+        }
+        state.person_index_handle.delete(String::from("2")).await.unwrap();
+        state.person_index_handle.flush().await.unwrap();
+        while state.person_index_handle.search("", 10, SearchOptions::default()).await.unwrap().len() != 1 {
+            state.person_index_handle.reload().unwrap();
+        }
+
+        let response = send(&router, Request::get("/people/count?query=empleos").body(Body::empty()).unwrap()).await;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()["count"], 1);
+
+        for (uri, num_docs) in [("/people/stats", 1), ("/questions/stats", 0)] {
+            let response = send(&router, Request::get(uri).body(Body::empty()).unwrap()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(stats["num_docs"], num_docs, "{}", uri);
+            assert_eq!(stats["reindexing"], false);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_analyze_a_text_as_indexed_or_searched() {
+        let router = routes(new_test_state().await);
+
+        let tokens = |uri: &'static str| {
+            let router = router.clone();
+            async move {
+                let response = send(&router, Request::get(uri).body(Body::empty()).unwrap()).await;
+                assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                let analyzed: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                analyzed["tokens"].as_array().unwrap().iter()
+                    .map(|token| (token["text"].as_str().unwrap().to_string(), token["position"].as_u64().unwrap()))
+                    .collect::<Vec<(String, u64)>>()
+            }
+        };
+
+        let expected = [("habi", 0), ("caball", 2)].map(|(text, position)| (String::from(text), position));
+        assert_eq!(tokens("/analyze?text=Hab%C3%ADa+un+caballo").await, expected);
+        assert_eq!(tokens("/analyze?text=Jo@X&analyzer=email_prefix").await.len(), 3);
+        assert_eq!(tokens("/analyze?text=Jo@X&analyzer=email_prefix&search=true").await.len(), 2);
+
+        let response = send(&router, Request::get("/analyze?text=caballo&analyzer=klingon").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn it_should_tell_whether_an_id_exists() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        send(&router, json_request("/questions", QUESTION)).await;
+        for person in [r#"{"id":"1","email":"ana@empleos.es"}"#, r#"{"id":"2","email":"luis@empleos.es"}"#] {
+            send(&router, json_request("/people", person)).await;
+        }
+        state.person_index_handle.delete(String::from("2")).await.unwrap();
+        for handle in [&state.question_index_handle, &state.person_index_handle] {
+            handle.flush().await.unwrap();
+        }
+        while !state.question_index_handle.exists("1").await.unwrap() || state.person_index_handle.exists("2").await.unwrap() {
+            state.question_index_handle.reload().unwrap();
+            state.person_index_handle.reload().unwrap();
+        }
+
+        for (uri, status) in [("/questions/1", StatusCode::OK), ("/questions/2", StatusCode::NOT_FOUND), ("/people/1", StatusCode::OK), ("/people/2", StatusCode::NOT_FOUND)] {
+            let response = send(&router, Request::head(uri).body(Body::empty()).unwrap()).await;
+            assert_eq!(response.status(), status, "{}", uri);
+            assert!(hyper::body::to_bytes(response.into_body()).await.unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_patch_the_email_of_a_person() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+        let patch = |id: &str| Request::patch(format!("/people/{}", id))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"email":"ana@gmail.com"}"#))
+            .unwrap();
+
+        assert_eq!(send(&router, patch("1")).await.status(), StatusCode::NOT_FOUND);
+
+        send(&router, json_request("/people", r#"{"id":"1","email":"ana@empleos.es"}"#)).await;
+        while state.person_index_handle.search("", 10, SearchOptions::default()).await.unwrap().is_empty() {
+            state.person_index_handle.commit(String::from("people")).await;
+        }
+
+        assert_eq!(send(&router, patch("1")).await.status(), StatusCode::ACCEPTED);
+        while state.person_index_handle.search("gmail", 10, SearchOptions::default()).await.unwrap().is_empty() {
+            state.person_index_handle.commit(String::from("people")).await;
+        }
+
+        let response = send(&router, Request::get("/people?domain=gmail.com").body(Body::empty()).unwrap()).await;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let people: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(people.len(), 1);
+        assert_eq!(people[0]["id"], "1");
+        assert_eq!(people[0]["email"], "ana@gmail.com");
+        assert!(state.person_index_handle.search("empleos", 10, SearchOptions::default()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_search_a_question_exactly_and_accent_sensitively_after_patching_its_popularity() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        send(&router, json_request("/questions", QUESTION)).await;
+        while state.question_index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().is_empty() {
+            state.question_index_handle.commit(String::from("questions")).await;
+        }
+
+        let patch = Request::patch("/questions/1")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"popularity":5}"#))
+            .unwrap();
+        assert_eq!(send(&router, patch).await.status(), StatusCode::ACCEPTED);
+        let ranked = SearchOptions { rank_field: new_question_schema().get_field("rank"), ..SearchOptions::default() };
+        while state.question_index_handle.search("caballo", 10, ranked.clone()).await.unwrap()[0].score != 5.0 {
+            state.question_index_handle.commit(String::from("questions")).await;
+        }
+
+        for uri in ["/questions?query=caballo&match=exact", "/questions?query=caballo&accent_sensitive=true"] {
+            let response = send(&router, Request::get(uri).body(Body::empty()).unwrap()).await;
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let questions: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+            assert_eq!(questions.len(), 1, "{}", uri);
+            assert_eq!(questions[0]["popularity"], 5, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_find_people_by_email_prefix() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        assert_eq!(send(&router, json_request("/people", r#"{"id":"1","email":"john.doe@example.com"}"#)).await.status(), StatusCode::ACCEPTED);
+        while state.person_index_handle.search("", 10, SearchOptions::default()).await.unwrap().is_empty() {
+            state.person_index_handle.commit(String::from("people")).await;
+        }
+
+        for (query, expected) in [("joh", 1), ("john", 1), ("JOHN.DO", 1), ("exam", 1), ("xyz", 0), ("johnny", 0)] {
+            let response = send(&router, Request::get(format!("/people?query={}", query)).body(Body::empty()).unwrap()).await;
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let people: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+            assert_eq!(people.len(), expected, "query {}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_limit_each_type_of_the_unified_search_independently() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        for id in ["1", "2", "3"] {
+            send(&router, json_request("/questions", &QUESTION.replace(r#""id":"1""#, &format!(r#""id":"{}""#, id)))).await;
+            send(&router, json_request("/people", &format!(r#"{{"id":"{}","email":"caballo.{}@empleos.es"}}"#, id, id))).await;
+        }
+        while state.question_index_handle.search("", 10, SearchOptions::default()).await.unwrap().len() < 3
+            || state.person_index_handle.search("", 10, SearchOptions::default()).await.unwrap().len() < 3 {
+            state.question_index_handle.commit(String::from(QUESTIONS_INDEX)).await;
+            state.person_index_handle.commit(String::from("people")).await;
+        }
+
+        let response = send(&router, Request::get("/search?query=caballo&questions_limit=2").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let results: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(results["questions"]["total"], 3);
+        assert_eq!(results["questions"]["hits"].as_array().unwrap().len(), 2);
+        assert_eq!(results["people"]["total"], 3);
+        assert_eq!(results["people"]["hits"].as_array().unwrap().len(), 3);
+        assert!(results["questions"]["took_ms"].is_u64());
+        assert!(results["people"]["took_ms"].is_u64());
+
+        // A zero limit still answers the best hit
+        let response = send(&router, Request::get("/search?query=caballo&questions_limit=0&people_limit=0").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let results: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(results["questions"]["hits"].as_array().unwrap().len(), 1);
+        assert_eq!(results["people"]["hits"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_should_rank_the_unified_hits_by_their_weighted_scores() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        send(&router, json_request("/questions", QUESTION)).await;
+        send(&router, json_request("/people", r#"{"id":"2","email":"caballo@empleos.es"}"#)).await;
+        while state.question_index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().is_empty()
+            || state.person_index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().is_empty() {
+            state.question_index_handle.commit(String::from(QUESTIONS_INDEX)).await;
+            state.person_index_handle.commit(String::from("people")).await;
+        }
+
+        let types = |weights: &str| {
+            let router = router.clone();
+            let uri = format!("/search?query=caballo&{}", weights);
+            async move {
+                let response = send(&router, Request::get(uri).body(Body::empty()).unwrap()).await;
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                let results: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                results["hits"].as_array().unwrap().iter().map(|hit| hit["type"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(types("questions_weight=100").await, vec!["question", "person"]);
+        assert_eq!(types("people_weight=100").await, vec!["person", "question"]);
+        assert_eq!(types("questions_weight=0&people_weight=0").await, vec!["question", "person"]);
+
+        let response = send(&router, Request::get("/search?query=caballo&people_weight=-1").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn it_should_stream_questions_as_ndjson_when_accepted() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        send(&router, json_request("/questions", QUESTION)).await;
+        send(&router, json_request("/questions", &QUESTION.replace(r#""id":"1""#, r#""id":"2""#))).await;
+
+        while state.question_index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().len() < 2 {
+            state.question_index_handle.commit(String::from("questions")).await;
+        }
+
+        let search_request = Request::get("/questions?query=caballo&fields=id")
+            .header(header::ACCEPT, "application/x-ndjson")
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&router, search_request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), "application/x-ndjson");
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let mut ids: Vec<serde_json::Value> = String::from_utf8(body.to_vec()).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["id"].clone())
+            .collect();
+        ids.sort_by_key(|id| id.to_string());
+        assert_eq!(ids, vec!["1", "2"]);
+    }
+
+    #[tokio::test]
+    async fn it_should_export_every_document_as_ndjson() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        for id in ["1", "2"] {
+            send(&router, json_request("/questions", &QUESTION.replace(r#""id":"1""#, &format!(r#""id":"{}""#, id)))).await;
+        }
+        while state.question_index_handle.search("", 10, SearchOptions::default()).await.unwrap().len() < 2 {
+            state.question_index_handle.commit(String::from(QUESTIONS_INDEX)).await;
+        }
+
+        let response = send(&router, Request::get("/questions/export").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), "application/x-ndjson");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let mut ids: Vec<serde_json::Value> = String::from_utf8(body.to_vec()).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["id"].clone())
+            .collect();
+        ids.sort_by_key(|id| id.to_string());
+        assert_eq!(ids, vec!["1", "2"]);
+
+        let response = send(&router, Request::get("/people/export").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(hyper::body::to_bytes(response.into_body()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_count_the_question_types_of_the_matching_questions() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        let questions = [("1", "caballo blanco", "ADMINISTRATION"), ("2", "caballo negro", "ADMINISTRATION"), ("3", "caballo pardo", "HEALTH"), ("4", "perro", "HEALTH")];
+        for (id, question, question_type) in questions {
+            let body = format!(r#"{{"id":"{}","question":"{}","public_employment_name":"Public Employment","question_type":"{}","created_at":"asd"}}"#, id, question, question_type);
+            send(&router, json_request("/questions", &body)).await;
+        }
+        while state.question_index_handle.search("", 10, SearchOptions::default()).await.unwrap().len() < 4 {
+            state.question_index_handle.commit(String::from(QUESTIONS_INDEX)).await;
+        }
+
+        let response = send(&router, Request::get("/questions?query=caballo&facet=question_type").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let results: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(results["results"].as_array().unwrap().len(), 3);
+        assert_eq!(results["facets"]["question_type"], serde_json::json!({ "ADMINISTRATION": 2, "HEALTH": 1 }));
+
+        let response = send(&router, Request::get("/questions?query=caballo&facet=lang").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn it_should_only_serve_searches_when_read_only() {
+        let mut handles = HashMap::new();
+        for definition in index_definitions() {
+            let name = definition.name;
+            let dir = RamDirectory::create();
+            Index::create(dir.clone(), definition.schema.clone(), IndexSettings::default()).unwrap();
+            let config = IndexerConfig { read_only: true, ..IndexerConfig::default() };
+            handles.insert(name, IndexActorHandle::new(dir, definition, config).await.unwrap());
+        }
+        let router = routes(AppState::new(handles, ServerConfig::default()));
+
+        let response = send(&router, Request::get("/questions?query=caballo").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert_eq!(send(&router, json_request("/questions", QUESTION)).await.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(send(&router, json_request("/people", r#"{"id":"1","email":"ana@empleos.es"}"#)).await.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let response = send(&router, Request::delete("/questions/1").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn it_should_ask_to_retry_searches_while_rebuilding_an_index() {
+        let mut handles = HashMap::new();
+        for definition in index_definitions() {
+            let name = definition.name;
+            let dir = RamDirectory::create();
+            if name == QUESTIONS_INDEX {
+                // An index with another schema is erased and must be fully reindexed
+                Index::create(dir.clone(), person_index().schema, IndexSettings::default()).unwrap();
+            }
+            handles.insert(name, IndexActorHandle::new(dir, definition, IndexerConfig::default()).await.unwrap());
+        }
+        let router = routes(AppState::new(handles, ServerConfig::default()));
+
+        let response = send(&router, Request::get("/questions?query=caballo").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "30");
+
+        let response = send(&router, Request::get("/people?query=ana").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn it_should_count_matching_documents() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        send(&router, json_request("/questions", QUESTION)).await;
+        send(&router, json_request("/people", r#"{"id":"1","email":"ana@empleos.es"}"#)).await;
+
+        while state.question_index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().is_empty()
+            || state.person_index_handle.search("ana", 10, SearchOptions::default()).await.unwrap().is_empty() {
+            state.question_index_handle.commit(String::from("questions")).await;
+            state.person_index_handle.commit(String::from("people")).await;
+        }
+
+        for (uri, expected) in [("/questions/count?query=caballo", 1), ("/questions/count?query=perro", 0), ("/people/count?domain=empleos.es", 1)] {
+            let response = send(&router, Request::get(uri).body(Body::empty()).unwrap()).await;
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let count: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(count, serde_json::json!({"count": expected}), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_validate_queries_without_searching() {
+        let router = routes(new_test_state().await);
+
+        let response = send(&router, Request::get("/questions/validate?query=caballo").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let validation: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(validation["query"].as_str().unwrap().contains("caball"));
+
+        let response = send(&router, Request::get("/questions/validate?query=unknown:caballo").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = send(&router, Request::get("/people/validate?query=*ana").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn it_should_reload_the_readers_on_demand() {
+        let state = new_test_state().await;
+        let router = routes(state.clone());
+
+        for uri in ["/questions/reader/reload", "/people/reader/reload"] {
+            let response = send(&router, Request::post(uri).body(Body::empty()).unwrap()).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let reader: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert!(reader["generation"].is_u64() && reader["opstamp"].is_u64(), "{}", uri);
+        }
+
+        send(&router, json_request("/questions", QUESTION)).await;
+        state.question_index_handle.commit(String::from("questions")).await;
+        while state.question_index_handle.reload().unwrap().opstamp == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(state.question_index_handle.search("caballo", 10, SearchOptions::default()).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_should_report_the_indexer_queues() {
+        let router = routes(new_test_state().await);
+
+        let response = send(&router, Request::get("/admin/indexers").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let indexers: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+
+        let names: Vec<&str> = indexers.iter().map(|i| i["name"].as_str().unwrap()).collect();
+        assert_eq!(names, vec!["questions", "people", "organizations"]);
+        for indexer in &indexers {
+            assert_eq!(indexer["alive"], true);
+            assert!(indexer["queue_free"].as_u64().unwrap() <= 8);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_require_the_api_key_to_change_indexes_only() {
+        let router = Router::new()
+            .route("/questions", get(|| async { StatusCode::OK }).post(|| async { StatusCode::ACCEPTED }))
+            .layer(middleware::from_fn_with_state(Some("secret".into()), require_api_key));
+
+        let response = send(&router, Request::get("/questions").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        for authorization in [None, Some("Bearer wrong"), Some("secret")] {
+            let mut request = Request::post("/questions");
+            if let Some(authorization) = authorization {
+                request = request.header(header::AUTHORIZATION, authorization);
+            }
+            let response = send(&router, request.body(Body::empty()).unwrap()).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{:?}", authorization);
+        }
+
+        let request = Request::post("/questions").header(header::AUTHORIZATION, "Bearer secret").body(Body::empty()).unwrap();
+        assert_eq!(send(&router, request).await.status(), StatusCode::ACCEPTED);
+    }
 }
\ No newline at end of file